@@ -1,69 +1,187 @@
 use serde::{ Deserialize, Serialize };
-use std::collections::HashMap;
+use sha256::digest;
+use std::collections::{ BTreeMap, HashMap };
 
+use crate::address::Address;
+use crate::block::Block;
+use crate::merkle::{ merkle_proof, merkle_root, ProofStep };
+use crate::transaction::{ Transaction, TxStatus };
+
+// Balances only ever change through consensus: genesis allocations, coinbase rewards and transfers
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Account {
-    pub accounts: Vec<String>,
-    pub balances: HashMap<String, f64>,
+    pub accounts: Vec<Address>,
+    pub balances: HashMap<Address, f64>,
+    // Number of successful transfers sent from each address; the next one must carry this nonce
+    #[serde(default)]
+    pub nonces: HashMap<Address, u32>,
 }
 
 impl Account {
     pub fn new() -> Self {
         Self {
-            accounts: vec![
-                String::from("230681c76f00b412ccf7757a8449c448a04acd735e497a7612b66d8bfcb8e576"),
-                String::from("5aede624154386ca358af195e13a46981b917ee8279f30a67d7a211a3d3e7243")
-            ],
-            balances: HashMap::from([
-                (
-                    String::from(
-                        "230681c76f00b412ccf7757a8449c448a04acd735e497a7612b66d8bfcb8e576"
-                    ),
-                    500.0,
-                ),
-                (
-                    String::from(
-                        "5aede624154386ca358af195e13a46981b917ee8279f30a67d7a211a3d3e7243"
-                    ),
-                    500.0,
-                ),
-            ]),
+            accounts: vec![],
+            balances: HashMap::new(),
+            nonces: HashMap::new(),
         }
     }
 
-    pub fn initialize(&mut self, address: &String) {
-        if !self.balances.contains_key(address) {
-            println!("New account: {}", address);
-            self.balances.insert(address.to_string(), 100.0);
-            self.accounts.push(address.to_string());
+    // Starting state taken from the genesis allocations
+    pub fn from_allocations(allocations: &BTreeMap<Address, f64>) -> Self {
+        Self {
+            accounts: allocations.keys().copied().collect(),
+            balances: allocations
+                .iter()
+                .map(|(address, balance)| (*address, *balance))
+                .collect(),
+            nonces: HashMap::new(),
         }
     }
 
-    pub fn transfer(&mut self, from: &String, to: &String, amount: &f64) {
-        self.initialize(from);
-        self.initialize(to);
-        self.increment(to, amount);
+    pub fn transfer(&mut self, from: &Address, to: &Address, amount: &f64) {
         self.decrement(from, amount);
+        self.increment(to, amount);
+    }
+
+    // Credit an address, creating its account on first receipt
+    pub fn increment(&mut self, to: &Address, amount: &f64) {
+        if !self.balances.contains_key(to) {
+            self.accounts.push(*to);
+        }
+        *self.balances.entry(*to).or_insert(0.0) += amount;
+    }
+
+    pub fn decrement(&mut self, from: &Address, amount: &f64) {
+        if let Some(balance) = self.balances.get_mut(from) {
+            *balance -= amount;
+        }
+    }
+
+    // Move the funds of an already validated transaction and consume the sender's nonce
+    pub fn settle(&mut self, txn: &Transaction) {
+        self.transfer(&txn.from_address, &txn.to_address, &txn.amount);
+        *self.nonces.entry(txn.from_address).or_insert(0) += 1;
+    }
+
+    // Apply a transaction if it is valid against the balances so far, reporting the outcome
+    pub fn apply(&mut self, txn: &Transaction) -> TxStatus {
+        if !txn.is_valid(self) {
+            return TxStatus::FAILED;
+        }
+        self.settle(txn);
+        TxStatus::SUCCESS
+    }
+
+    // Settle a sealed block: its successful transfers in order, then the coinbase reward,
+    // which therefore cannot be spent in the same block
+    pub fn execute_block(&mut self, block: &Block, mining_reward: f64) {
+        for txn in &block.transactions {
+            if txn.status == TxStatus::SUCCESS {
+                self.settle(txn);
+            }
+        }
+        if let Some(miner) = &block.miner {
+            self.increment(miner, &mining_reward);
+        }
     }
 
-    pub fn increment(&mut self, to: &String, amount: &f64) {
-        *self.balances.get_mut(to).unwrap() += amount;
+    // Addresses that never received anything simply hold nothing
+    pub fn get_balance(&self, address: &Address) -> f64 {
+        self.balances.get(address).copied().unwrap_or(0.0)
     }
 
-    pub fn decrement(&mut self, from: &String, amount: &f64) {
-        *self.balances.get_mut(from).unwrap() -= amount;
+    pub fn get_nonce(&self, address: &Address) -> u32 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    // Addresses in the order their leaves appear in the state root
+    pub fn sorted_addresses(&self) -> Vec<Address> {
+        let mut addresses: Vec<Address> = self.balances.keys().copied().collect();
+        addresses.sort();
+        addresses
+    }
+
+    pub fn state_leaf(&self, address: &Address) -> String {
+        digest(format!("{}:{}:{}", address, self.get_balance(address), self.get_nonce(address)))
+    }
+
+    // Commitment to every balance and nonce, identical on every node holding the same state
+    pub fn state_root(&self) -> String {
+        let leaves: Vec<String> = self
+            .sorted_addresses()
+            .iter()
+            .map(|address| self.state_leaf(address))
+            .collect();
+        merkle_root(&leaves)
+    }
+
+    // Proof that an address's leaf is part of state_root, none for unknown addresses
+    pub fn state_proof(&self, address: &Address) -> Option<Vec<ProofStep>> {
+        let addresses = self.sorted_addresses();
+        let index = addresses.binary_search(address).ok()?;
+        let leaves: Vec<String> = addresses
+            .iter()
+            .map(|address| self.state_leaf(address))
+            .collect();
+        merkle_proof(&leaves, index)
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::verify_proof;
+    use secp256k1::SecretKey;
+
+    const SENDER: &str = "duma1pac4ht6afshdx2tctnhjnetz7u6g3j9z7vz07y";
+    const RECIPIENT: &str = "duma1k8ye8rcpzg0ptxy84skg6wf6ytjywmlce5h424";
+
+    fn account() -> Account {
+        let allocations = BTreeMap::from([
+            (SENDER.parse().unwrap(), 500.0),
+            (RECIPIENT.parse().unwrap(), 25.5),
+        ]);
+        Account::from_allocations(&allocations)
+    }
+
+    #[test]
+    fn state_root_commits_to_balances_and_nonces() {
+        assert_eq!(Account::new().state_root(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        let mut account = account();
+        assert_eq!(account.state_root(), "cc471910da65d56032642f70e87c5d83e6628e46e8a4ad9ab183390fb97682c5");
+
+        let mut secret = [0u8; 32];
+        secret[31] = 1;
+        let secret_key = SecretKey::from_slice(&secret).unwrap();
+        let txn = Transaction::new_signed(&secret_key, &"0".repeat(64), RECIPIENT.parse().unwrap(), 1.5, 0);
+        assert_eq!(account.apply(&txn), TxStatus::SUCCESS);
+        assert_eq!(account.state_root(), "2a0d01a076598ede7064dc3f597f4a1df91dc6686ff581088f064df87a2c857b");
     }
 
-    pub fn get_balance(&self, address: &String) -> &f64 {
-        // self.initialize(address);
-        self.balances.get(address).unwrap()
+    #[test]
+    fn state_root_ignores_insertion_order() {
+        let mut reversed = Account::new();
+        reversed.increment(&RECIPIENT.parse().unwrap(), &25.5);
+        reversed.increment(&SENDER.parse().unwrap(), &500.0);
+        assert_eq!(reversed.state_root(), account().state_root());
     }
 
-    pub fn is_valid_address(&self, address: &String) -> bool {
-        if !self.balances.contains_key(address) {
-            return false;
-        } else {
-            return true;
+    #[test]
+    fn state_proofs_verify_against_the_root() {
+        let account = account();
+        let root = account.state_root();
+        for address in [SENDER, RECIPIENT] {
+            let address: Address = address.parse().unwrap();
+            let proof = account.state_proof(&address).unwrap();
+            assert!(verify_proof(&account.state_leaf(&address), &proof, &root));
         }
+        let unknown: Address = "duma1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqcrww0v".parse().unwrap();
+        assert!(account.state_proof(&unknown).is_none());
     }
 }
@@ -0,0 +1,132 @@
+use bech32::{ primitives::decode::CheckedHrpstring, Bech32m, Hrp };
+use ring::digest::{ digest, SHA256 };
+use secp256k1::PublicKey;
+use serde::{ de, Deserialize, Deserializer, Serialize, Serializer };
+use std::{ fmt, str::FromStr };
+use utoipa::{ openapi::{ schema::{ ObjectBuilder, Type }, RefOr, Schema }, PartialSchema, ToSchema };
+
+// Human readable prefix of every address, e.g. duma1qyqs...
+pub const ADDRESS_HRP: &str = "duma";
+pub const ADDRESS_LEN: usize = 20;
+
+// Account identifier: the first 20 bytes of SHA-256 over the compressed public key,
+// written as Bech32m so typos are caught by the checksum
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address([u8; ADDRESS_LEN]);
+
+impl Address {
+    pub fn from_public_key(public_key: &PublicKey) -> Self {
+        let hash = digest(&SHA256, &public_key.serialize());
+        let mut bytes = [0u8; ADDRESS_LEN];
+        bytes.copy_from_slice(&hash.as_ref()[..ADDRESS_LEN]);
+        Address(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; ADDRESS_LEN] {
+        &self.0
+    }
+}
+
+impl From<&PublicKey> for Address {
+    fn from(public_key: &PublicKey) -> Self {
+        Address::from_public_key(public_key)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = Hrp::parse_unchecked(ADDRESS_HRP);
+        let encoded = bech32::encode_lower::<Bech32m>(hrp, &self.0).map_err(|_| fmt::Error)?;
+        f.write_str(&encoded)
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address({})", self)
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> anyhow::Result<Self> {
+        let checked = CheckedHrpstring::new::<Bech32m>(address).map_err(|e|
+            anyhow::anyhow!("Invalid address '{}': {}", address, e)
+        )?;
+        if checked.hrp().as_str() != ADDRESS_HRP {
+            return Err(anyhow::anyhow!("Address '{}' must start with {}1", address, ADDRESS_HRP));
+        }
+        let bytes: Vec<u8> = checked.byte_iter().collect();
+        let bytes: [u8; ADDRESS_LEN] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Address '{}' has the wrong length", address))?;
+        Ok(Address(bytes))
+    }
+}
+
+// Documented as the Bech32m string it is serialized as
+impl PartialSchema for Address {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .schema_type(Type::String)
+            .description(Some("Bech32m account address"))
+            .examples(["duma1yvrgr3m0qz6p9n8hw4aggjwyfzsy4ntn69ply2"])
+            .into()
+    }
+}
+
+impl ToSchema for Address {}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::{ Secp256k1, SecretKey };
+
+    fn public_key(secret: u8) -> PublicKey {
+        let mut bytes = [0u8; 32];
+        bytes[31] = secret;
+        PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn encodes_known_public_keys() {
+        let address = Address::from_public_key(&public_key(1));
+        assert_eq!(hex::encode(address.as_bytes()), "0f715baf5d4c2ed329785cef29e562f73488c8a2");
+        assert_eq!(address.to_string(), "duma1pac4ht6afshdx2tctnhjnetz7u6g3j9z7vz07y");
+        assert_eq!(Address::from_public_key(&public_key(2)).to_string(), "duma1k8ye8rcpzg0ptxy84skg6wf6ytjywmlce5h424");
+        assert_eq!(Address([0; ADDRESS_LEN]).to_string(), "duma1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqcrww0v");
+    }
+
+    #[test]
+    fn parses_what_it_prints() {
+        let address = Address::from_public_key(&public_key(1));
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+        assert_eq!(serde_json::from_str::<Address>(&serde_json::to_string(&address).unwrap()).unwrap(), address);
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        // One character changed
+        assert!("duma1pac4ht6afshdx2tctnhjnetz7u6g3j9z7vz07z".parse::<Address>().is_err());
+        // Another prefix
+        assert!("bc1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqwu0tn9".parse::<Address>().is_err());
+        // Bech32 checksum instead of Bech32m
+        assert!("duma1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqdl7z2w".parse::<Address>().is_err());
+        // 19 bytes
+        assert!("duma1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqujdz72".parse::<Address>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use crate::address::Address;
+use crate::block::BlockHeader;
+use crate::blockchain::{ ChainInfo, TxPosition };
+use crate::keystore::Keystore;
+use crate::merkle::ProofStep;
+use crate::sync::SyncProgress;
+use crate::transaction::Transaction;
+use axum::{ http::StatusCode, response::{ IntoResponse, Response }, Json };
+use serde::{ Deserialize, Serialize };
+use utoipa::ToSchema;
+
+// Bodies of the HTTP API. Every successful response is wrapped in ApiResponse and every
+// failure is an ApiError, so clients only ever parse these two shapes
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiResponse<T> {
+    /// Always "success"
+    pub status: String,
+    pub data: T,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T) -> Json<Self> {
+        Json(ApiResponse { status: "success".to_string(), data })
+    }
+}
+
+pub type ApiResult<T> = Result<Json<ApiResponse<T>>, ApiError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip, default = "internal_error")]
+    pub code: StatusCode,
+    pub error: String,
+    /// Set when the request may be repeated after this many seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+fn internal_error() -> StatusCode {
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+impl ApiError {
+    pub fn new(code: StatusCode, error: impl ToString) -> Self {
+        ApiError { code, error: error.to_string(), retry_after_secs: None }
+    }
+
+    pub fn bad_request(error: impl ToString) -> Self {
+        ApiError::new(StatusCode::BAD_REQUEST, error)
+    }
+
+    pub fn not_found(error: impl ToString) -> Self {
+        ApiError::new(StatusCode::NOT_FOUND, error)
+    }
+
+    pub fn internal(error: impl ToString) -> Self {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code, Json(self)).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChainValidity {
+    pub is_valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransactionReceipt {
+    pub tx: Transaction,
+}
+
+/// A mined transaction and where it sits in the chain
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChainTransaction {
+    pub height: usize,
+    /// Position inside the block
+    pub index: usize,
+    pub tx: Transaction,
+}
+
+impl ChainTransaction {
+    pub fn new(position: TxPosition, tx: Transaction) -> Self {
+        ChainTransaction { height: position.height, index: position.index, tx }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransactionPage {
+    pub transactions: Vec<ChainTransaction>,
+    /// Pass as `cursor` to fetch the next page; null on the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransactionProof {
+    pub tx: Transaction,
+    pub height: usize,
+    pub block_hash: String,
+    pub merkle_root: String,
+    pub proof: Vec<ProofStep>,
+}
+
+/// A fresh key; the secret key is only returned when no password was given, otherwise it
+/// is inside the keystore
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewWallet {
+    pub address: Address,
+    pub public_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keystore: Option<Keystore>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewHdWallet {
+    pub mnemonic: String,
+    pub path: String,
+    /// First address of the path
+    pub address: Address,
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HdAddress {
+    pub index: u32,
+    pub address: Address,
+    pub public_key: String,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RestoredHdWallet {
+    pub path: String,
+    pub addresses: Vec<HdAddress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WalletSummary {
+    pub address: Address,
+    pub public_key: String,
+    pub balance: f64,
+    /// Nonce of the next transfer, counting ones still in the mempool
+    pub nonce: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Balance {
+    pub address: Address,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WalletTransactions {
+    pub address: Address,
+    pub transactions: Vec<ChainTransaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Headers {
+    /// Height of the node's tip
+    pub height: usize,
+    pub headers: Vec<BlockHeader>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StateProof {
+    pub address: Address,
+    pub balance: f64,
+    pub nonce: u32,
+    pub leaf: String,
+    pub proof: Vec<ProofStep>,
+    pub height: usize,
+    pub block_hash: String,
+    pub state_root: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NodeStatus {
+    pub chain: ChainInfo,
+    pub total_supply: f64,
+    pub average_block_time_secs: Option<f64>,
+    pub network_hashrate: Option<f64>,
+    pub peers: usize,
+    pub sync: SyncProgress,
+    pub version: String,
+    pub uptime_secs: u64,
+}
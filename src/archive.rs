@@ -0,0 +1,190 @@
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use anyhow::Context;
+use ring::digest::{ Context as Digest, SHA256 };
+use serde::{ Deserialize, Serialize };
+use std::{ fs::{ self, File }, io::{ BufRead, BufReader, BufWriter, Write }, path::Path };
+
+pub const ARCHIVE_FORMAT: &str = "duma-chain-archive";
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// Portable chain export: a header line, one JSON block per line and a trailer line
+// holding the block count and the SHA-256 of every byte before it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveHeader {
+    pub format: String,
+    pub version: u32,
+    pub chain_id: String,
+    pub genesis_hash: String,
+    // Height of the first block in the file; blocks follow in height order
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveTrailer {
+    pub blocks: usize,
+    pub checksum: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub skipped: usize,
+    pub imported: usize,
+    pub height: usize,
+}
+
+// Write blocks from..=to of the chain; `to` defaults to the tip
+pub fn export(blockchain: &Blockchain, from: usize, to: Option<usize>, out: &Path) -> anyhow::Result<ArchiveHeader> {
+    let to = to.unwrap_or(blockchain.height());
+    if from > to || to > blockchain.height() {
+        return Err(anyhow::anyhow!("Invalid range {}..={}, chain height is {}", from, to, blockchain.height()));
+    }
+    let header = ArchiveHeader {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        chain_id: blockchain.genesis.chain_id.clone(),
+        genesis_hash: blockchain.genesis_hash(),
+        from,
+        to,
+    };
+
+    // Written aside and renamed so a half written archive never looks complete
+    let tmp = out.with_extension("partial");
+    {
+        let mut file = BufWriter::new(File::create(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?);
+        let mut checksum = Digest::new(&SHA256);
+        let mut write_line = |line: String| -> anyhow::Result<()> {
+            let line = line + "\n";
+            checksum.update(line.as_bytes());
+            file.write_all(line.as_bytes())?;
+            Ok(())
+        };
+        write_line(serde_json::to_string(&header)?)?;
+        for block in &blockchain.chain[from..=to] {
+            write_line(serde_json::to_string(block)?)?;
+        }
+        let trailer = ArchiveTrailer {
+            blocks: to - from + 1,
+            checksum: hex::encode(checksum.finish()),
+        };
+        writeln!(file, "{}", serde_json::to_string(&trailer)?)?;
+        file.into_inner()?.sync_all()?;
+    }
+    fs::rename(&tmp, out)?;
+    Ok(header)
+}
+
+// Check the format, block count and checksum without applying anything
+pub fn verify(path: &Path) -> anyhow::Result<ArchiveHeader> {
+    let mut checksum = Digest::new(&SHA256);
+    let mut header: Option<ArchiveHeader> = None;
+    let mut trailer: Option<ArchiveTrailer> = None;
+    let mut blocks = 0;
+    for (line_number, line) in lines(path)? {
+        let line = line?;
+        if trailer.is_some() {
+            return Err(anyhow::anyhow!("Unexpected data after the trailer at {}:{}", path.display(), line_number));
+        }
+        match header {
+            None => {
+                let parsed: ArchiveHeader = serde_json
+                    ::from_str(&line)
+                    .with_context(|| format!("{} has no archive header", path.display()))?;
+                if parsed.format != ARCHIVE_FORMAT || parsed.version != ARCHIVE_VERSION {
+                    return Err(
+                        anyhow::anyhow!("Unsupported archive {} version {}", parsed.format, parsed.version)
+                    );
+                }
+                header = Some(parsed);
+            }
+            Some(_) => {
+                // Only the last line is the trailer, anything else counts as a block
+                if let Ok(parsed) = serde_json::from_str::<ArchiveTrailer>(&line) {
+                    trailer = Some(parsed);
+                    continue;
+                }
+                blocks += 1;
+            }
+        }
+        checksum.update(line.as_bytes());
+        checksum.update(b"\n");
+    }
+
+    let header = header.ok_or_else(|| anyhow::anyhow!("{} is empty", path.display()))?;
+    let trailer = trailer.ok_or_else(||
+        anyhow::anyhow!("{} has no trailer, the export is incomplete", path.display())
+    )?;
+    if hex::encode(checksum.finish()) != trailer.checksum {
+        return Err(anyhow::anyhow!("{} failed its checksum", path.display()));
+    }
+    if blocks != trailer.blocks || header.to < header.from || blocks != header.to - header.from + 1 {
+        return Err(
+            anyhow::anyhow!(
+                "{} holds {} blocks but claims {} for heights {}..={}",
+                path.display(),
+                blocks,
+                trailer.blocks,
+                header.from,
+                header.to
+            )
+        );
+    }
+    Ok(header)
+}
+
+// Apply an archive on top of the chain. Blocks we already hold must match ours and are
+// skipped; every new block goes through full validation and is handed to `applied` in order
+pub fn import(
+    blockchain: &mut Blockchain,
+    path: &Path,
+    mut applied: impl FnMut(&Block) -> anyhow::Result<()>
+) -> anyhow::Result<ImportSummary> {
+    let header = verify(path)?;
+    if header.chain_id != blockchain.genesis.chain_id || header.genesis_hash != blockchain.genesis_hash() {
+        return Err(
+            anyhow::anyhow!("{} belongs to chain {} with a different genesis", path.display(), header.chain_id)
+        );
+    }
+    if header.from > blockchain.chain.len() {
+        return Err(
+            anyhow::anyhow!(
+                "{} starts at height {} but the chain is only at {}",
+                path.display(),
+                header.from,
+                blockchain.height()
+            )
+        );
+    }
+
+    let mut summary = ImportSummary { skipped: 0, imported: 0, height: blockchain.height() };
+    // Skip the header, then stop before the trailer
+    let block_lines = lines(path)?.skip(1).take(header.to - header.from + 1);
+    for (height, (line_number, line)) in (header.from..).zip(block_lines) {
+        let block: Block = serde_json
+            ::from_str(&line?)
+            .with_context(|| format!("Corrupt block at {}:{}", path.display(), line_number))?;
+        if let Some(ours) = blockchain.chain.get(height) {
+            if ours.hash != block.hash {
+                return Err(anyhow::anyhow!("{} diverges from our chain at height {}", path.display(), height));
+            }
+            summary.skipped += 1;
+            continue;
+        }
+        blockchain.add_block(block.clone()).with_context(|| format!("Invalid block at height {}", height))?;
+        applied(&block)?;
+        summary.imported += 1;
+    }
+    summary.height = blockchain.height();
+    Ok(summary)
+}
+
+fn lines(path: &Path) -> anyhow::Result<impl Iterator<Item = (usize, std::io::Result<String>)>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+    )
+}
@@ -0,0 +1,169 @@
+use crate::api::ApiError;
+use crate::rate_limit::RateLimiter;
+use axum::{
+    extract::{ MatchedPath, Request, State },
+    http::{ header::{ AUTHORIZATION, WWW_AUTHENTICATE }, HeaderName, HeaderValue, Method, StatusCode },
+    middleware::Next,
+    response::{ IntoResponse, Response },
+};
+use serde::{ Deserialize, Serialize };
+use std::{ collections::HashMap, fmt, sync::Arc };
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+// What a client may do; each role includes the ones below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Only for anonymous_role: clients without a key get nothing
+    None,
+    // Query the chain, wallets and node status
+    Read,
+    // Also send transactions and use the faucet and key generation helpers
+    Submit,
+    // Also spend from the node wallet and any future node management routes
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::None => "none",
+            Role::Read => "read",
+            Role::Submit => "submit",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+// One client allowed past anonymous access; only a hash of the key is configured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub role: Role,
+    // Hex SHA-256 of the key, as printed by `node api-key`
+    pub key_hash: String,
+    // Replaces rate_limit_per_minute for this key; routes with their own quota keep it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_per_minute: Option<u32>,
+}
+
+// Name of the API key a request was made with, for layers that account per client
+#[derive(Debug, Clone)]
+pub struct ApiClient(pub String);
+
+// Role the route needs. Reads are open to every role; anything that changes state needs
+// submit unless it is listed as admin here
+pub fn required_role(method: &Method, route: &str) -> Role {
+    match (method, route) {
+        (&Method::POST, "/wallet/transfer") => Role::Admin,
+        (&Method::GET | &Method::HEAD | &Method::OPTIONS, _) => Role::Read,
+        // Read-only methods are the common case; the handler checks the rest per call
+        (&Method::POST, "/rpc") => Role::Read,
+        _ => Role::Submit,
+    }
+}
+
+pub fn hash_key(key: &str) -> String {
+    sha256::digest(key)
+}
+
+// New random key, returned with the hash to put in the config
+pub fn generate_key() -> anyhow::Result<(String, String)> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)?;
+    let key = hex::encode(bytes);
+    let hash = hash_key(&key);
+    Ok((key, hash))
+}
+
+// Attach a key to an outgoing request from one of our clients, as `Authorization: Bearer`
+pub fn with_api_key(request: ureq::Request, api_key: Option<&str>) -> ureq::Request {
+    match api_key {
+        Some(key) => request.set(AUTHORIZATION.as_str(), &format!("Bearer {}", key)),
+        None => request,
+    }
+}
+
+pub struct Auth {
+    anonymous_role: Role,
+    // Keyed by key hash
+    keys: HashMap<String, ApiKeyConfig>,
+    // Unknown keys are refused here, before the rate limit layer sees the request
+    limiter: Arc<RateLimiter>,
+}
+
+impl Auth {
+    pub fn new(anonymous_role: Role, keys: &[ApiKeyConfig], limiter: Arc<RateLimiter>) -> Self {
+        Auth {
+            anonymous_role,
+            limiter,
+            keys: keys
+                .iter()
+                .map(|key| (key.key_hash.to_ascii_lowercase(), key.clone()))
+                .collect(),
+        }
+    }
+
+    // Configured entry for a presented key, None if the key is unknown
+    fn find_key(&self, key: &str) -> Option<&ApiKeyConfig> {
+        self.keys.get(&hash_key(key))
+    }
+}
+
+// `Authorization: Bearer <key>`, or the key alone in `X-API-Key`
+fn presented_key(request: &Request) -> Option<&str> {
+    let headers = request.headers();
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer.or_else(|| headers.get(API_KEY_HEADER).and_then(|value| value.to_str().ok())).map(str::trim)
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut response = ApiError::new(StatusCode::UNAUTHORIZED, message).into_response();
+    response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+// Resolve the caller's role, refuse the request if the route needs more, and leave the
+// role in the request extensions for handlers that check per call
+pub async fn authenticate(State(auth): State<Arc<Auth>>, mut request: Request, next: Next) -> Response {
+    let api_key = match presented_key(&request) {
+        Some(key) =>
+            match auth.find_key(key) {
+                Some(api_key) => Some(api_key),
+                None => {
+                    if let Some(response) = auth.limiter.charge_ip(&request) {
+                        return response;
+                    }
+                    return unauthorized("Unknown API key");
+                }
+            }
+        None => None,
+    };
+    let role = api_key.map_or(auth.anonymous_role, |api_key| api_key.role);
+    if let Some(api_key) = api_key {
+        tracing::Span::current().record("client", api_key.name.as_str());
+    }
+    // Unmatched paths fall through to the 404 handler
+    let required = match request.extensions().get::<MatchedPath>() {
+        Some(route) => required_role(request.method(), route.as_str()),
+        None => Role::None,
+    };
+    if role < required {
+        let message = format!("This route needs the {} role", required);
+        return match api_key {
+            Some(_) => ApiError::new(StatusCode::FORBIDDEN, message).into_response(),
+            None => unauthorized(&message),
+        };
+    }
+    request.extensions_mut().insert(role);
+    if let Some(api_key) = api_key {
+        request.extensions_mut().insert(ApiClient(api_key.name.clone()));
+    }
+    next.run(request).await
+}
@@ -0,0 +1,331 @@
+use advanced_db_blockchain::{
+    address::Address,
+    auth,
+    genesis::GenesisSpec,
+    hd_wallet::{ DerivationPath, HdWallet, DEFAULT_DERIVATION_PATH },
+    keystore::Keystore,
+    light_client::LightClient,
+    transaction::Transaction,
+    wallet::Wallet,
+};
+use anyhow::Context;
+use clap::{ Parser, Subcommand };
+use serde_json::Value;
+use std::{ fs, io::Read, path::{ Path, PathBuf } };
+
+// Offline key management and signing; only public data and signed transactions go to the node
+#[derive(Parser, Debug)]
+#[command(name = "wallet", about = "Duma command-line wallet")]
+struct WalletCli {
+    /// Base URL of the node HTTP API
+    #[arg(long, env = "WALLET_NODE", default_value = "http://127.0.0.1:7000", global = true)]
+    node: String,
+    /// API key for nodes that refuse anonymous clients, sent as a bearer token
+    #[arg(long, env = "API_KEY", hide_env_values = true, global = true)]
+    api_key: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create a new key and store it in an encrypted keystore
+    New {
+        #[arg(long)]
+        keystore: PathBuf,
+        /// Derive the key from a fresh mnemonic, printed once so it can be written down
+        #[arg(long)]
+        mnemonic: bool,
+        #[arg(long, default_value_t = 12)]
+        words: usize,
+        #[arg(long, default_value = DEFAULT_DERIVATION_PATH)]
+        path: String,
+        #[arg(long, default_value_t = 0)]
+        index: u32,
+    },
+    /// Restore a key from its mnemonic into a keystore; the phrase is read from the terminal or WALLET_MNEMONIC
+    Restore {
+        #[arg(long)]
+        keystore: PathBuf,
+        #[arg(long, default_value = DEFAULT_DERIVATION_PATH)]
+        path: String,
+        #[arg(long, default_value_t = 0)]
+        index: u32,
+    },
+    /// Encrypt an existing hex secret key into a keystore; the key is read from the terminal or WALLET_SECRET_KEY
+    Import {
+        #[arg(long)]
+        keystore: PathBuf,
+    },
+    /// Show the address and public key of a keystore
+    Address {
+        #[arg(long)]
+        keystore: PathBuf,
+    },
+    /// Query the balance of an address
+    Balance {
+        address: Option<String>,
+        #[arg(long, conflicts_with = "address")]
+        keystore: Option<PathBuf>,
+    },
+    /// List transactions sent from or to an address
+    History {
+        address: Option<String>,
+        #[arg(long, conflicts_with = "address")]
+        keystore: Option<PathBuf>,
+    },
+    /// Build and sign a transfer, printing the signed transaction as JSON
+    Sign {
+        #[arg(long)]
+        keystore: PathBuf,
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: f64,
+        /// Sign fully offline with this nonce and --genesis-hash instead of asking the node
+        #[arg(long)]
+        nonce: Option<u32>,
+        /// Genesis block hash of the chain to sign for, as shown by `node status`
+        #[arg(long)]
+        genesis_hash: Option<String>,
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Submit a signed transaction from a file, or stdin when omitted
+    Submit {
+        file: Option<PathBuf>,
+    },
+    /// Sign and submit a transfer in one step
+    Send {
+        #[arg(long)]
+        keystore: PathBuf,
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: f64,
+    },
+    /// Check a transaction against block headers only, without trusting the node
+    Confirm {
+        /// Transaction id, shown as `id`
+        txid: String,
+        #[arg(long, default_value_t = 1)]
+        confirmations: usize,
+        #[arg(long, default_value = "genesis.json")]
+        genesis_file: PathBuf,
+        /// Difficulty the node runs with, when it overrides the genesis spec
+        #[arg(long)]
+        difficulty: Option<usize>,
+    },
+}
+
+fn main() {
+    let cli = WalletCli::parse();
+    if let Err(e) = run(cli) {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+// Where requests go and the key they carry
+struct Node {
+    url: String,
+    api_key: Option<String>,
+}
+
+fn run(cli: WalletCli) -> anyhow::Result<()> {
+    let node = Node { url: cli.node.trim_end_matches('/').to_string(), api_key: cli.api_key };
+    match cli.command {
+        Command::New { keystore, mnemonic, words, path, index } => {
+            let wallet = if mnemonic {
+                let hd_wallet = HdWallet::generate(words, path.parse()?)?;
+                eprintln!("Write down this mnemonic, it is the only backup of the key:\n\n{}\n", hd_wallet.mnemonic());
+                hd_wallet.derive(index)?
+            } else {
+                Wallet::new()
+            };
+            save(&wallet, &keystore)
+        }
+        Command::Restore { keystore, path, index } => {
+            let phrase = secret_input("WALLET_MNEMONIC", "Mnemonic: ")?;
+            let passphrase = std::env::var("WALLET_MNEMONIC_PASSPHRASE").unwrap_or_default();
+            let path: DerivationPath = path.parse()?;
+            let wallet = HdWallet::restore(phrase.trim(), &passphrase, path)?.derive(index)?;
+            save(&wallet, &keystore)
+        }
+        Command::Import { keystore } => {
+            let secret_key = secret_input("WALLET_SECRET_KEY", "Secret key (hex): ")?;
+            save(&Wallet::import_secret_key(secret_key.trim())?, &keystore)
+        }
+        Command::Address { keystore } => {
+            let keystore = Keystore::load(&keystore)?;
+            let public_key = keystore.public_key.parse().context("Invalid keystore public key")?;
+            print_json(
+                &serde_json::json!({
+                "address": Address::from_public_key(&public_key),
+                "public_key": keystore.public_key,
+            })
+            )
+        }
+        Command::Balance { address, keystore } => {
+            let address = resolve_address(address, keystore)?;
+            print_json(&get(&node, &format!("/wallet/{}/balance", address))?)
+        }
+        Command::History { address, keystore } => {
+            let address = resolve_address(address, keystore)?;
+            print_json(&get(&node, &format!("/wallet/{}/transactions", address))?)
+        }
+        Command::Sign { keystore, to, amount, nonce, genesis_hash, out } => {
+            let wallet = unlock(&keystore)?;
+            let transaction = sign(&node, &wallet, &to, amount, nonce, genesis_hash)?;
+            let signed = serde_json::to_string_pretty(&transaction)?;
+            match out {
+                Some(out) => fs::write(&out, signed).with_context(|| format!("Failed to write {}", out.display())),
+                None => {
+                    println!("{}", signed);
+                    Ok(())
+                }
+            }
+        }
+        Command::Submit { file } => {
+            let signed = match file {
+                Some(file) => fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?,
+                None => {
+                    let mut signed = String::new();
+                    std::io::stdin().read_to_string(&mut signed)?;
+                    signed
+                }
+            };
+            let transaction: Transaction = serde_json::from_str(&signed).context("Invalid signed transaction")?;
+            print_json(&post(&node, "/transaction/submit", &transaction)?)
+        }
+        Command::Send { keystore, to, amount } => {
+            let wallet = unlock(&keystore)?;
+            let transaction = sign(&node, &wallet, &to, amount, None, None)?;
+            print_json(&post(&node, "/transaction/submit", &transaction)?)
+        }
+        Command::Confirm { txid, confirmations, genesis_file, difficulty } => {
+            let mut genesis = GenesisSpec::load(&genesis_file)?;
+            if let Some(difficulty) = difficulty {
+                genesis.initial_difficulty = difficulty;
+            }
+            let mut client = LightClient::new(&node.url, &genesis).with_api_key(node.api_key.clone());
+            let confirmed = client.is_confirmed(&txid, confirmations)?;
+            let verified = client.verify_transaction(&txid)?;
+            print_json(
+                &serde_json::json!({
+                "confirmed": confirmed,
+                "height": client.height(),
+                "transaction": verified,
+            })
+            )
+        }
+    }
+}
+
+// Secrets come from an environment variable for scripts, otherwise a prompt that does not echo
+fn secret_input(env: &str, prompt: &str) -> anyhow::Result<String> {
+    match std::env::var(env) {
+        Ok(value) => Ok(value),
+        Err(_) => Ok(rpassword::prompt_password(prompt)?),
+    }
+}
+
+fn password(confirm: bool) -> anyhow::Result<String> {
+    if let Ok(password) = std::env::var("WALLET_PASSWORD") {
+        return Ok(password);
+    }
+    let password = rpassword::prompt_password("Keystore password: ")?;
+    if confirm && rpassword::prompt_password("Repeat password: ")? != password {
+        return Err(anyhow::Error::msg("Passwords do not match"));
+    }
+    Ok(password)
+}
+
+fn save(wallet: &Wallet, keystore: &Path) -> anyhow::Result<()> {
+    wallet.save_keystore(keystore, &password(true)?)?;
+    print_json(
+        &serde_json::json!({
+        "address": wallet.address(),
+        "public_key": wallet.public_key().to_string(),
+        "keystore": keystore,
+    })
+    )
+}
+
+fn unlock(keystore: &Path) -> anyhow::Result<Wallet> {
+    Wallet::unlock(keystore, &password(false)?)
+}
+
+fn resolve_address(address: Option<String>, keystore: Option<PathBuf>) -> anyhow::Result<Address> {
+    match (address, keystore) {
+        (Some(address), _) => address.parse(),
+        (None, Some(keystore)) => {
+            let public_key = Keystore::load(&keystore)?.public_key.parse().context("Invalid keystore public key")?;
+            Ok(Address::from_public_key(&public_key))
+        }
+        (None, None) => Err(anyhow::Error::msg("Give an address or --keystore")),
+    }
+}
+
+fn sign(
+    node: &Node,
+    wallet: &Wallet,
+    to: &str,
+    amount: f64,
+    nonce: Option<u32>,
+    genesis_hash: Option<String>
+) -> anyhow::Result<Transaction> {
+    let to_address: Address = to.parse()?;
+    if !(amount > 0.0 && amount.is_finite()) {
+        return Err(anyhow::Error::msg("Amount must be positive"));
+    }
+    let nonce = match nonce {
+        Some(nonce) => nonce,
+        None => {
+            let details = get(node, &format!("/wallet/{}", wallet.public_key()))?;
+            details["data"]["nonce"]
+                .as_u64()
+                .and_then(|nonce| u32::try_from(nonce).ok())
+                .ok_or_else(|| anyhow::Error::msg("Node did not return a nonce"))?
+        }
+    };
+    let genesis_hash = match genesis_hash {
+        Some(genesis_hash) => genesis_hash,
+        None => {
+            let status = get(node, "/status")?;
+            status["data"]["chain"]["genesis_hash"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::Error::msg("Node did not return its genesis hash"))?
+        }
+    };
+    Ok(Transaction::new_signed(&wallet.secret_key(), &genesis_hash, to_address, amount, nonce))
+}
+
+fn get(node: &Node, path: &str) -> anyhow::Result<Value> {
+    let request = ureq::get(&format!("{}{}", node.url, path));
+    response(auth::with_api_key(request, node.api_key.as_deref()).call())
+}
+
+fn post(node: &Node, path: &str, body: &impl serde::Serialize) -> anyhow::Result<Value> {
+    let request = ureq::post(&format!("{}{}", node.url, path));
+    response(auth::with_api_key(request, node.api_key.as_deref()).send_json(body))
+}
+
+// Turn the node's {"error": ...} bodies into readable errors
+fn response(result: Result<ureq::Response, ureq::Error>) -> anyhow::Result<Value> {
+    match result {
+        Ok(response) => Ok(response.into_json()?),
+        Err(ureq::Error::Status(status, response)) => {
+            let body: Value = response.into_json().unwrap_or_default();
+            let message = body["error"].as_str().unwrap_or("request failed").to_string();
+            Err(anyhow::anyhow!("node returned {}: {}", status, message))
+        }
+        Err(e) => Err(anyhow::Error::new(e).context("Cannot reach node")),
+    }
+}
+
+fn print_json(value: &Value) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
@@ -1,107 +1,181 @@
-use secp256k1::ecdsa::Signature;
 use serde::{ Deserialize, Serialize };
 use crate::account::Account;
-use crate::transaction::{ self, Transaction };
+use crate::address::Address;
+use crate::transaction::{ Transaction, TxStatus };
 use crate::blockchain::Blockchain;
+use crate::merkle::{ merkle_proof, merkle_root, ProofStep };
+use crate::metrics::METRICS;
 use sha256::digest;
+use std::time::{ Instant, SystemTime };
+use tracing::{ info, info_span };
+use utoipa::ToSchema;
+
+// How far ahead of our clock a block timestamp may be
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+
+// Header fields covered by the proof of work, enough to validate a chain without bodies
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct BlockHeader {
+    pub timestamp: u64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    // Account state root after the block is applied
+    pub state_root: String,
+    pub nonce: u32,
+    pub difficulty: usize,
+    pub hash: String,
+}
+
+impl BlockHeader {
+    pub fn calculate_hash(&self) -> String {
+        let data = format!(
+            "{}{}{}{}{}{}",
+            self.timestamp,
+            self.previous_hash,
+            self.merkle_root,
+            self.state_root,
+            self.nonce,
+            self.difficulty
+        );
+        digest(data)
+    }
+
+    // Check that the stored hash matches the header and meets its difficulty
+    pub fn has_valid_proof(&self) -> bool {
+        self.hash == self.calculate_hash() && self.hash.starts_with(&"0".repeat(self.difficulty))
+    }
+
+    // More than MAX_FUTURE_BLOCK_TIME ahead of our clock; such a block may become valid later
+    pub fn is_too_far_in_future(&self) -> bool {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+        self.timestamp > now + MAX_FUTURE_BLOCK_TIME
+    }
+
+    // Validate headers following `parent_hash`: linkage, proof of work and timestamps
+    pub fn validate_chain(parent_hash: &str, headers: &[BlockHeader], difficulty: usize) -> anyhow::Result<()> {
+        let mut previous_hash = parent_hash;
+        for header in headers {
+            if header.previous_hash != previous_hash {
+                return Err(anyhow::Error::msg("Headers are not linked"));
+            }
+            if header.difficulty != difficulty || !header.has_valid_proof() {
+                return Err(anyhow::Error::msg("Invalid header proof of work"));
+            }
+            if header.is_too_far_in_future() {
+                return Err(anyhow::Error::msg("Header timestamp too far in the future"));
+            }
+            previous_hash = &header.hash;
+        }
+        Ok(())
+    }
+}
 
 // Block structure
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Block {
     pub timestamp: u64,
     pub transactions: Vec<Transaction>,
     pub previous_hash: String,
     pub hash: String,
     pub nonce: u32,
+    pub difficulty: usize,
     pub block_capacity: usize, // Maximum number of transactions per block
     pub mined: bool,
+    // Address credited with the coinbase reward, none for genesis
+    #[serde(default)]
+    pub miner: Option<Address>,
+    // Commitment to every balance and nonce once this block is applied
+    pub state_root: String,
 }
 
 impl Block {
+    // The coinbase leaf comes first so the proof of work also commits to the reward address
+    fn merkle_leaves(&self) -> Vec<String> {
+        let coinbase = self.miner.iter().map(|miner| digest(format!("coinbase{}", miner)));
+        coinbase.chain(self.transactions.iter().map(|txn| txn.hash())).collect()
+    }
+
+    pub fn merkle_root(&self) -> String {
+        merkle_root(&self.merkle_leaves())
+    }
+
+    // Proof that a transaction, found by its id, is included under merkle_root
+    pub fn transaction_proof(&self, id: &str) -> Option<(&Transaction, Vec<ProofStep>)> {
+        let index = self.transactions.iter().position(|txn| txn.id == id)?;
+        let proof = merkle_proof(&self.merkle_leaves(), index + usize::from(self.miner.is_some()))?;
+        Some((&self.transactions[index], proof))
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash.clone(),
+            merkle_root: self.merkle_root(),
+            state_root: self.state_root.clone(),
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            hash: self.hash.clone(),
+        }
+    }
+
     // Calculate block hash
     pub fn calculate_hash(&self) -> String {
-        let data = format!(
-            "{}{:?}{}{}",
-            self.timestamp,
-            self.transactions,
-            self.previous_hash,
-            self.nonce
-        );
-        digest(data)
+        self.header().calculate_hash()
+    }
+
+    // Check that the stored hash matches the block content and meets the difficulty
+    pub fn has_valid_proof(&self) -> bool {
+        self.header().has_valid_proof()
     }
 
     pub fn execute_txn(&self, blockchain: &mut Blockchain) {
-        self.transactions.iter().for_each(|txn| {
-            if txn.status.eq(&transaction::TxStatus::FAILED) {
-                return;
-            }
-            blockchain.accounts.transfer(&txn.from_address, &txn.to_address, &txn.amount);
-        });
+        blockchain.execute_txn(self);
     }
 
-    pub fn mine_block_with_capacity(&mut self, difficulty: usize, account: &Account, force: bool) {
-        println!("\n⛏️  Let's start mining and simulating transactions!\n");
+    pub fn mine_block_with_capacity(&mut self, account: &Account, mining_reward: f64) {
+        let _span = info_span!(
+            "block",
+            previous = %self.previous_hash,
+            transactions = self.transactions.len()
+        ).entered();
         if self.mined {
             return; // Block already mined, exit early
         }
-        // Check if the number of transactions has reached the block capacity
-        if force {
-            // println!("force: {}", force);
-            if self.mined == false {
-                for transaction in &mut self.transactions {
-                    // println!("Mined transaction: {:?}", &transaction);
-                    if transaction.is_valid(account) == false {
-                        // println!("tx invalid: {:#?}", transaction);
-                        transaction.status = transaction::TxStatus::FAILED;
-                    } else {
-                        // println!("Valid transaction: {:?}", transaction);
-                        transaction.status = transaction::TxStatus::SUCCESS;
-                    }
-                }
-                self.hash = self.calculate_hash(); // Initialize hash with the calculated hash
-                while &self.hash[..difficulty] != &"0".repeat(difficulty) {
-                    // println!("block hash: {}", self.hash);
-                    self.nonce += 1;
-                    self.hash = self.calculate_hash(); // Recalculate hash with updated nonce
-                }
-                // println!("Block mined: {}", self.hash);
-                // println!("Hash: {}", self.calculate_hash());
-                self.mined = true;
-                // self.hash = self.calculate_hash();
-            }
-        } else {
-            // if self.transactions.len() == self.block_capacity && self.mined == false {
-            if self.mined == false {
-                // Initialize hash with the calculated hash
-                self.hash = self.calculate_hash(); // Initialize hash with the calculated hash
-                let mut i = 0;
-                while &self.hash[..difficulty] != &"0".repeat(difficulty) {
-                    i += 1;
-                    println!("Block mined {}: {:?}", i, self.hash);
-                    self.nonce += 1; // Increment the nonce
-                    // Recalculate the hash with updated nonce and any other block data
-                    self.hash = self.calculate_hash();
-                }
-                // println!("Hash: {}", self.calculate_hash());
-
-                for transaction in &mut self.transactions {
-                    // println!("Mined transaction: {:?}", &transaction);
-
-                    if !transaction.is_valid(account) {
-                        // println!("tx invalid: {:#?}", transaction);
-                        transaction.status = transaction::TxStatus::FAILED;
-                    } else {
-                        transaction.status = transaction::TxStatus::SUCCESS;
-                    }
-                }
-                self.mined = true;
-                // self.hash = self.calculate_hash();
-                println!("🧱 Mining block {:?}...⛏️", self.hash);
-            }
+        // Transaction statuses are part of the hashed data, so settle them before mining.
+        // Each one is checked against the balances left by the ones before it, and those that
+        // would fail are left out since blocks may only hold successful transfers.
+        let mut accounts = account.clone();
+        self.transactions.retain_mut(|transaction| {
+            transaction.status = accounts.apply(transaction);
+            transaction.status == TxStatus::SUCCESS
+        });
+        if let Some(miner) = &self.miner {
+            accounts.increment(miner, &mining_reward);
+        }
+        self.state_root = accounts.state_root();
+        // The body is fixed from here on, so only the header needs rehashing
+        let started = Instant::now();
+        let mut attempts: u64 = 1;
+        let mut header = self.header();
+        header.hash = header.calculate_hash(); // Initialize hash with the calculated hash
+        while !header.hash.starts_with(&"0".repeat(header.difficulty)) {
+            header.nonce += 1; // Increment the nonce
+            header.hash = header.calculate_hash();
+            attempts += 1;
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        METRICS.blocks_mined.inc();
+        METRICS.mining_duration.observe(elapsed);
+        if elapsed > 0.0 {
+            METRICS.hashrate.set(attempts as f64 / elapsed);
         }
+        self.nonce = header.nonce;
+        self.hash = header.hash;
+        self.mined = true;
+        info!(hash = %self.hash, nonce = self.nonce, attempts, elapsed_ms = elapsed * 1000.0, "🧱 Mined block");
     }
 
-    pub fn find_transaction_by_signature(&self, msg: &str) -> Option<&Transaction> {
-        self.transactions.iter().find(|txn| { txn.msg == msg })
+    pub fn find_transaction(&self, id: &str) -> Option<&Transaction> {
+        self.transactions.iter().find(|txn| txn.id == id)
     }
 }
@@ -1,10 +1,115 @@
 use serde::{ Deserialize, Serialize };
-use crate::block::Block;
-use crate::transaction::{ self, Transaction };
-use std::time::SystemTime;
+use crate::block::{ Block, BlockHeader };
+use crate::transaction::{ self, Transaction, TxStatus };
+use std::{ collections::HashSet, fmt, str::FromStr, time::SystemTime };
 use crate::account::Account;
+use crate::address::Address;
+use crate::genesis::GenesisSpec;
+use crate::metrics::METRICS;
 use crate::wallet::Wallet;
 use anyhow::Result;
+use tokio::sync::broadcast;
+use tracing::{ debug, info_span, warn };
+use utoipa::ToSchema;
+
+// Events emitted whenever the chain or mempool changes
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    BlockConnected {
+        height: usize,
+        block: Block,
+    },
+    // Blocks above fork_height were replaced by a longer chain, whose blocks follow as
+    // BlockConnected events
+    Reorg {
+        fork_height: usize,
+        disconnected: Vec<Block>,
+    },
+    // A transaction entered the mempool: submitted here, relayed by a peer or put back by a reorg
+    TransactionAccepted(Transaction),
+}
+
+// Recent blocks used to estimate block time and network hash rate
+pub const HASHRATE_WINDOW: usize = 20;
+
+// Pending transactions a node holds unless configured otherwise
+pub const DEFAULT_MEMPOOL_CAPACITY: usize = 5000;
+
+// Summary of the chain served by the status APIs
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ChainInfo {
+    pub chain_id: String,
+    pub genesis_hash: String,
+    pub height: usize,
+    pub tip_hash: String,
+    pub difficulty: usize,
+    pub block_capacity: usize,
+    pub mining_reward: f64,
+    pub pending_transactions: usize,
+}
+
+// Position of a transaction in the chain, used as the cursor when paging through them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TxPosition {
+    pub height: usize,
+    pub index: usize,
+}
+
+impl fmt::Display for TxPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.height, self.index)
+    }
+}
+
+impl FromStr for TxPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(cursor: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid cursor '{}'", cursor);
+        let (height, index) = cursor.split_once('-').ok_or_else(invalid)?;
+        Ok(TxPosition {
+            height: height.parse().map_err(|_| invalid())?,
+            index: index.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    // Oldest first
+    #[default]
+    Asc,
+    Desc,
+}
+
+// Criteria a listed transaction has to meet; bounds are inclusive
+#[derive(Debug, Clone, Default)]
+pub struct TxFilter {
+    pub status: Option<TxStatus>,
+    // Sender or recipient
+    pub address: Option<Address>,
+    pub min_amount: Option<f64>,
+    pub max_amount: Option<f64>,
+    pub from_height: Option<usize>,
+    pub to_height: Option<usize>,
+}
+
+impl TxFilter {
+    fn matches(&self, tx: &Transaction) -> bool {
+        self.status.as_ref().is_none_or(|status| &tx.status == status) &&
+            self.address.is_none_or(|address| tx.from_address == address || tx.to_address == address) &&
+            self.min_amount.is_none_or(|min| tx.amount >= min) &&
+            self.max_amount.is_none_or(|max| tx.amount <= max)
+    }
+}
+
+// One page of transactions and where the next one starts, None on the last page
+#[derive(Debug, Clone)]
+pub struct TxPage {
+    pub transactions: Vec<(TxPosition, Transaction)>,
+    pub next_cursor: Option<TxPosition>,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Blockchain {
@@ -14,20 +119,51 @@ pub struct Blockchain {
     pub mining_reward: f64,
     pub accounts: Account,
     pub wallet: Wallet,
+    pub genesis: GenesisSpec,
+    // Where blocks mined by this node send their coinbase reward
+    #[serde(default)]
+    pub miner_address: Option<Address>,
+    // Most transactions kept pending; submissions beyond it are refused
+    #[serde(default = "default_mempool_capacity")]
+    pub mempool_capacity: usize,
+    #[serde(skip)]
+    pub events: Option<broadcast::Sender<ChainEvent>>,
 }
 
 impl Blockchain {
-    // Create genesis block
-    pub fn create_genesis_block() -> Block {
-        Block {
-            block_capacity: 4,
-            timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
+    // Start a chain at the genesis block and allocations described by the spec
+    pub fn from_genesis(genesis: GenesisSpec, wallet: Wallet) -> Self {
+        Blockchain {
+            chain: vec![Blockchain::create_genesis_block(&genesis)],
+            difficulty: genesis.initial_difficulty,
+            pending_transactions: vec![],
+            mining_reward: genesis.mining_reward,
+            accounts: Account::from_allocations(&genesis.allocations),
+            wallet,
+            genesis,
+            miner_address: None,
+            mempool_capacity: DEFAULT_MEMPOOL_CAPACITY,
+            events: None,
+        }
+    }
+
+    // Create genesis block, identical on every node using the same spec.
+    // It has no parent, so previous_hash carries the spec commitment instead.
+    pub fn create_genesis_block(spec: &GenesisSpec) -> Block {
+        let mut genesis = Block {
+            block_capacity: spec.block_capacity,
+            timestamp: spec.timestamp,
             transactions: vec![],
-            previous_hash: "0".to_string(),
+            previous_hash: spec.commitment(),
             hash: String::new(),
             nonce: 0,
-            mined: false,
-        }
+            difficulty: 0,
+            mined: true,
+            miner: None,
+            state_root: Account::from_allocations(&spec.allocations).state_root(),
+        };
+        genesis.hash = genesis.calculate_hash();
+        genesis
     }
 
     // Get latest block in the chain
@@ -39,160 +175,326 @@ impl Blockchain {
         self
     }
 
-    // Add transaction to pending transactions
-    pub fn add_transaction(
-        &mut self,
-        transaction: Transaction
-    ) -> Result<Transaction, anyhow::Error> {
-        if let Some(lastest_block) = self.chain.last_mut() {
-            // if lastest_block.transactions.len() == lastest_block.block_capacity - 1 {
-            // Mine the current block if it has reached its transaction capacity
-            lastest_block.transactions.push(transaction.clone());
-            lastest_block.nonce += 1;
-            lastest_block.mine_block_with_capacity(self.difficulty, &self.accounts, false);
-            let prev_block = lastest_block.clone();
-            // println!("Nonce: {}", &lastest_block.nonce);
-            // Create a new block for the incoming transaction
-            let new_block = &mut self.create_new_block(&prev_block);
-            self.chain.push(new_block.clone());
-            return Ok(transaction.clone());
-        } else {
-            // If there are no blocks in the chain, create the genesis block
-            let genesis_block = &mut Blockchain::create_genesis_block();
-            genesis_block.nonce += 1;
-
-            // println!("GenesisBlock: {:?}", &genesis_block);
-
-            self.chain.push(genesis_block.clone());
-            // Add the transaction to the genesis block's transactions
-            self.chain[0].transactions.push(transaction.clone());
-            return Ok(transaction.clone());
-        }
-    }
-
-    pub fn add_new_tx(&mut self, transaction: Transaction) -> Result<Transaction, anyhow::Error> {
-        if let Some(lastest_block) = self.chain.last_mut() {
-            if self.pending_transactions.len() < lastest_block.block_capacity {
-                println!("Add new");
-                self.pending_transactions.push(transaction.clone());
-                println!("Tx pending: {}", self.pending_transactions.len());
-                Ok(transaction.clone())
-            } else {
-                return Err(anyhow::Error::msg("Block is full, pls wait bruhhhh!"));
+    // Height of the tip, genesis being height 0
+    pub fn height(&self) -> usize {
+        self.chain.len().saturating_sub(1)
+    }
+
+    pub fn genesis_hash(&self) -> String {
+        self.chain.first().map(|block| block.hash.clone()).unwrap_or_default()
+    }
+
+    // Subscribe to chain events, creating the channel on first use
+    pub fn subscribe(&mut self) -> broadcast::Receiver<ChainEvent> {
+        match &self.events {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = broadcast::channel(256);
+                self.events = Some(sender);
+                receiver
             }
-        } else {
-            return Err(anyhow::Error::msg("Block is full"));
         }
     }
 
-    // pub asycn fn start_mining_loop(&mut self, interval_seconds: u64) {
-    //     let blockchain_ref = Arc::new(Mutex::new(self.clone()));
+    fn emit(&self, event: ChainEvent) {
+        if let Some(sender) = &self.events {
+            let _ = sender.send(event);
+        }
+    }
 
-    //     thread::spawn(move || {
-    //         loop {
-    //             thread::sleep(Duration::from_secs(interval_seconds));
-    //             let mut blockchain = blockchain_ref.lock().await;
-    //             blockchain.mine_pending_transactions();
-    //         }
-    //     });
-    // }
+    // The one way into the mempool for local and relayed transactions alike: refuse ones
+    // that would only fail, then queue and announce the rest for the miner
+    pub fn submit_transaction(&mut self, mut transaction: Transaction) -> Result<Transaction> {
+        let _span = info_span!("transaction", id = %transaction.id).entered();
+        transaction.status = transaction::TxStatus::PENDING;
+        if !transaction.verify_signature() {
+            return Err(reject_transaction("invalid_signature", anyhow::Error::msg("Invalid transaction signature")));
+        }
+        if transaction.genesis_hash != self.genesis_hash() {
+            let error = anyhow::Error::msg("Transaction is signed for another chain");
+            return Err(reject_transaction("wrong_chain", error));
+        }
+        if self.chain.iter().any(|block| block.find_transaction(&transaction.id).is_some()) {
+            return Err(reject_transaction("duplicate", anyhow::Error::msg("Transaction is already in the chain")));
+        }
+        if self.pending_transactions.iter().any(|pending| pending.id == transaction.id) {
+            return Err(reject_transaction("duplicate", anyhow::Error::msg("Transaction is already in the mempool")));
+        }
+        let accounts = self.pending_accounts();
+        if transaction.nonce != accounts.get_nonce(&transaction.from_address) {
+            let error = anyhow::anyhow!(
+                "Invalid nonce {}, expected {}",
+                transaction.nonce,
+                accounts.get_nonce(&transaction.from_address)
+            );
+            return Err(reject_transaction("invalid_nonce", error));
+        }
+        if !transaction.is_valid(&accounts) {
+            let error = anyhow::Error::msg("Insufficient funds or invalid sender");
+            return Err(reject_transaction("insufficient_funds", error));
+        }
+        if self.pending_transactions.len() >= self.mempool_capacity {
+            return Err(reject_transaction("mempool_full", anyhow::Error::msg("Mempool is full, try again later")));
+        }
+        self.pending_transactions.push(transaction.clone());
+        debug!(pending = self.pending_transactions.len(), "Added transaction to the mempool");
+        self.emit(ChainEvent::TransactionAccepted(transaction.clone()));
+        METRICS.transactions_accepted.inc();
+        Ok(transaction)
+    }
 
-    // Mine pending transactions into a new block
-    pub fn mine_pending_transactions(&mut self) {
+    // Unmined block of pending transactions on top of the tip, with the account state it
+    // settles against, or None when nothing is pending. The mempool keeps the transactions
+    // until the mined block is added, in case the tip moves in the meantime
+    pub fn block_template(&self) -> Result<Option<(Block, Account)>> {
         if self.pending_transactions.is_empty() {
-            println!("No pending transactions");
-            return;
+            return Ok(None);
         }
+        let take = self.genesis.block_capacity.min(self.pending_transactions.len());
+        let block = self.create_new_block(self.pending_transactions[..take].to_vec())?;
+        Ok(Some((block, self.accounts.clone())))
+    }
 
-        if let Some(lastest_block) = self.chain.last_mut() {
-            // let mut new_block = &mut self.create_new_block(lastest_block.clone());
+    // Mine the next block from whatever is pending, even if that is nothing
+    pub fn mine_block(&mut self) -> Result<Block> {
+        let take = self.genesis.block_capacity.min(self.pending_transactions.len());
+        let transactions: Vec<Transaction> = self.pending_transactions.drain(..take).collect();
+        let mut new_block = self.create_new_block(transactions)?;
+        new_block.mine_block_with_capacity(&self.accounts, self.mining_reward);
+        self.connect_block(new_block.clone());
+        Ok(new_block)
+    }
 
-            while
-                !self.pending_transactions.is_empty() &&
-                lastest_block.transactions.len() < lastest_block.block_capacity
-            {
-                let transaction = self.pending_transactions.remove(0);
-                lastest_block.transactions.push(transaction);
+    // Drop pending transactions the current state no longer allows, e.g. because a block
+    // from a peer spent the same nonce, keeping the rest in order and within capacity
+    fn revalidate_mempool(&mut self) {
+        let mut accounts = self.accounts.clone();
+        let mut kept = Vec::with_capacity(self.pending_transactions.len());
+        for txn in std::mem::take(&mut self.pending_transactions) {
+            if kept.len() < self.mempool_capacity && txn.is_valid(&accounts) {
+                accounts.apply(&txn);
+                kept.push(txn);
+            } else {
+                debug!(id = %txn.id, "Dropped transaction from the mempool");
             }
-            println!("Begin process");
-            lastest_block.mine_block_with_capacity(self.difficulty, &self.accounts, false);
-            // self.execute_txn(lastest_block);
-            // self.create_new_block(&lastest_block.clone());
-            // let new_block = self.create_new_block(&lastest_block.clone());
-
-            let cloned_block = lastest_block.clone(); // Clone the block to avoid borrowing issues
-
-            self.execute_txn(&cloned_block);
-            // self.chain.push(new_block);
-            // let new_block = self.create_new_block(lastest_block.clone());
-
-            // println!("Block mined: {:?}", new_block);
-        } else {
-            let genesis_block = &mut Blockchain::create_genesis_block();
-            self.chain.push(genesis_block.clone());
         }
+        self.pending_transactions = kept;
     }
 
     // Validate the integrity of the blockchain
     pub fn is_chain_valid(&self) -> bool {
         for (i, block) in self.chain.iter().enumerate().skip(1) {
             let previous_block = &self.chain[i - 1];
-            if block.hash.is_empty() {
-                continue;
-            }
             if block.hash != block.calculate_hash() {
-                println!(
-                    "incorrect block hash at block num: {}, {} || {}",
-                    i,
-                    block.hash,
-                    block.calculate_hash()
-                );
+                warn!(height = i, hash = %block.hash, expected = %block.calculate_hash(), "Incorrect block hash");
+                return false;
+            }
+            if block.difficulty != self.difficulty || !block.has_valid_proof() {
+                warn!(height = i, hash = %block.hash, "Insufficient proof of work");
                 return false;
             }
             if block.previous_hash != previous_block.hash {
-                println!("not match");
-
+                warn!(height = i, hash = %block.hash, "Block does not link to its parent");
                 return false;
             }
         }
         true
     }
 
-    pub fn execute_chain(&mut self, chain: &Vec<Block>) {
+    // Check a block received from elsewhere against the current tip and account state
+    pub fn validate_block(&self, block: &Block) -> Result<()> {
+        let tip = self.get_latest_block().ok_or_else(|| anyhow::Error::msg("Empty chain"))?;
+        if block.previous_hash != tip.hash {
+            return Err(anyhow::Error::msg("Block does not extend the current tip"));
+        }
+        // The same rule header sync applies, so no path accepts a block peers would refuse
+        if block.header().is_too_far_in_future() {
+            return Err(anyhow::Error::msg("Block timestamp too far in the future"));
+        }
+        if block.difficulty != self.difficulty || !block.has_valid_proof() {
+            return Err(anyhow::Error::msg("Invalid block hash or proof of work"));
+        }
+        if block.transactions.len() > self.genesis.block_capacity {
+            return Err(anyhow::Error::msg("Block exceeds capacity"));
+        }
+        let genesis_hash = self.genesis_hash();
+        if !block.transactions.iter().all(|txn| txn.verify_signature() && txn.genesis_hash == genesis_hash) {
+            return Err(anyhow::Error::msg("Block holds a transaction not signed for this chain"));
+        }
+        // A transaction id may appear once in the whole chain
+        let mut ids = HashSet::new();
+        for txn in &block.transactions {
+            if !ids.insert(&txn.id) || self.chain.iter().any(|known| known.find_transaction(&txn.id).is_some()) {
+                return Err(anyhow::anyhow!("Block repeats transaction {}", txn.id));
+            }
+        }
+        // Only transfers that succeed may be mined. A failed one would burn its id without
+        // using its nonce, so a miner could block a victim's pending transfer for good
+        let mut accounts = self.accounts.clone();
+        for txn in &block.transactions {
+            if txn.status != TxStatus::SUCCESS || accounts.apply(txn) != TxStatus::SUCCESS {
+                return Err(anyhow::anyhow!("Block holds failed transaction {}", txn.id));
+            }
+        }
+        if let Some(miner) = &block.miner {
+            accounts.increment(miner, &self.mining_reward);
+        }
+        if block.state_root != accounts.state_root() {
+            return Err(anyhow::Error::msg("Block state root does not match the resulting account state"));
+        }
+        Ok(())
+    }
+
+    // Height of our block that the given header builds on, if any
+    pub fn find_ancestor(&self, header: &BlockHeader) -> Option<usize> {
+        self.chain.iter().position(|block| block.hash == header.previous_hash)
+    }
+
+    // Validate a header chain without bodies: linkage, proof of work and timestamps
+    pub fn validate_headers(&self, parent_hash: &str, headers: &[BlockHeader]) -> Result<()> {
+        BlockHeader::validate_chain(parent_hash, headers, self.difficulty)
+    }
+
+    // Hashes from the tip back to genesis, dense near the tip and sparse further back
+    pub fn locator(&self) -> Vec<String> {
+        let mut locator = Vec::new();
+        let mut height = self.height() as isize;
+        let mut step = 1;
+        while height > 0 {
+            locator.push(self.chain[height as usize].hash.clone());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height -= step;
+        }
+        locator.push(self.genesis_hash());
+        locator
+    }
+
+    // Headers following the first locator hash we know about
+    pub fn headers_after(&self, locator: &[String], max: usize) -> Vec<BlockHeader> {
+        let start = locator
+            .iter()
+            .find_map(|hash| self.chain.iter().position(|block| &block.hash == hash))
+            .unwrap_or(0);
+        self.chain
+            .iter()
+            .skip(start + 1)
+            .take(max)
+            .map(|block| block.header())
+            .collect()
+    }
+
+    // Validate and append a block mined by another node
+    pub fn add_block(&mut self, block: Block) -> Result<()> {
+        self.validate_block(&block)?;
+        self.pending_transactions.retain(|pending| block.find_transaction(&pending.id).is_none());
+        self.connect_block(block);
+        self.revalidate_mempool();
+        Ok(())
+    }
+
+    // Replace our chain with a longer valid one that shares our genesis
+    pub fn try_replace_chain(&mut self, chain: Vec<Block>) -> Result<bool> {
+        if chain.len() <= self.chain.len() {
+            return Ok(false);
+        }
+        if chain.first().map(|block| &block.hash) != self.chain.first().map(|block| &block.hash) {
+            return Err(anyhow::Error::msg("Chain has a different genesis block"));
+        }
+        let mut candidate = Blockchain::from_genesis(self.genesis.clone(), self.wallet.clone());
+        for block in chain.into_iter().skip(1) {
+            candidate.add_block(block)?;
+        }
+        if !candidate.is_chain_valid() {
+            return Err(anyhow::Error::msg("Candidate chain failed validation"));
+        }
+        let fork_point = self.chain
+            .iter()
+            .zip(candidate.chain.iter())
+            .take_while(|(ours, theirs)| ours.hash == theirs.hash)
+            .count();
+        let disconnected = std::mem::replace(&mut self.chain, candidate.chain).split_off(fork_point);
+        self.accounts = candidate.accounts;
+        // Transactions only the old branch had go back in front of the mempool, oldest first
+        let mut requeued: Vec<Transaction> = disconnected
+            .iter()
+            .flat_map(|block| block.transactions.iter().cloned())
+            .map(|mut txn| {
+                txn.status = TxStatus::PENDING;
+                txn
+            })
+            .collect();
+        let requeued_ids: HashSet<String> = requeued
+            .iter()
+            .map(|txn| txn.id.clone())
+            .collect();
+        requeued.append(&mut self.pending_transactions);
+        let chain = &self.chain;
+        self.pending_transactions = requeued
+            .into_iter()
+            .filter(|pending| chain.iter().all(|block| block.find_transaction(&pending.id).is_none()))
+            .collect();
+        self.revalidate_mempool();
+        if !disconnected.is_empty() {
+            self.emit(ChainEvent::Reorg { fork_height: fork_point - 1, disconnected });
+        }
+        for (height, block) in self.chain.iter().enumerate().skip(fork_point) {
+            self.emit(ChainEvent::BlockConnected { height, block: block.clone() });
+        }
+        for txn in self.pending_transactions.iter().filter(|txn| requeued_ids.contains(&txn.id)) {
+            self.emit(ChainEvent::TransactionAccepted(txn.clone()));
+        }
+        Ok(true)
+    }
+
+    pub fn execute_chain(&mut self, chain: &[Block]) {
         chain.iter().for_each(|block| self.execute_txn(block));
     }
 
     pub fn execute_txn(&mut self, block: &Block) {
-        block.transactions.iter().for_each(|txn| {
-            if txn.status.eq(&transaction::TxStatus::FAILED) {
-                return;
-            }
+        self.accounts.execute_block(block, self.mining_reward);
+    }
 
-            println!("Duma send tv hz: {:?}", &txn);
-            self.accounts.transfer(&txn.from_address, &txn.to_address, &txn.amount);
-        });
+    // Account state as of the block at `height`, replayed from genesis unless it is the tip
+    pub fn accounts_at(&self, height: usize) -> Option<Account> {
+        if height == self.height() {
+            return Some(self.accounts.clone());
+        }
+        let blocks = self.chain.get(1..=height)?;
+        let mut accounts = Account::from_allocations(&self.genesis.allocations);
+        for block in blocks {
+            accounts.execute_block(block, self.mining_reward);
+        }
+        Some(accounts)
+    }
 
-        // block.transactions.iter().for_each(|txn| {
-        //     // Transfer amount
-        //     println!("Txn: {:?}", txn);
-        //     self.accounts.transfer(&txn.from_address, &txn.to_address, &txn.amount);
-        //     // Transfer fee
-        //     // println!("Balance after transfer: {}", self.accounts.get_balance(&txn.from_address));
-        //     // println!("Balance after transfer: {}", self.accounts.get_balance(&txn.to_address));
-        // });
+    // Apply a sealed block to account state and append it to the chain
+    fn connect_block(&mut self, block: Block) {
+        let _span = info_span!("block", height = self.chain.len(), hash = %block.hash).entered();
+        self.execute_txn(&block);
+        debug!(transactions = block.transactions.len(), "Connected block");
+        self.emit(ChainEvent::BlockConnected { height: self.chain.len(), block: block.clone() });
+        self.chain.push(block);
     }
 
-    fn create_new_block(&mut self, block: &Block) -> Block {
-        block.execute_txn(self);
-        Block {
-            block_capacity: 4,
+    fn create_new_block(&self, transactions: Vec<Transaction>) -> Result<Block> {
+        let previous_hash = self
+            .get_latest_block()
+            .ok_or_else(|| anyhow::Error::msg("Empty chain"))?
+            .hash.clone();
+        Ok(Block {
+            block_capacity: self.genesis.block_capacity,
             timestamp: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
-            transactions: vec![],
-            previous_hash: self.get_latest_block().unwrap().hash.clone(),
+            transactions,
+            previous_hash,
             hash: String::new(),
             nonce: 0,
+            difficulty: self.difficulty,
             mined: false,
-        }
+            miner: self.miner_address,
+            state_root: String::new(),
+        })
     }
 
     pub fn get_all_blocks(&self) -> Vec<Block> {
@@ -201,8 +503,7 @@ impl Blockchain {
 
     pub fn get_all_tx(&self) -> Vec<Transaction> {
         let mut txs: Vec<Transaction> = Vec::new();
-        for (i, block) in self.chain.iter().enumerate() {
-            println!("Block num: {}", i);
+        for block in self.chain.iter() {
             for tx in block.transactions.iter() {
                 txs.push(tx.clone());
             }
@@ -210,7 +511,163 @@ impl Blockchain {
         txs
     }
 
-    pub fn get_balance(&self, public_key: &String) -> &f64 {
-        self.accounts.get_balance(public_key)
+    // Up to `limit` transactions matching the filter, continuing after `cursor`. Only the
+    // blocks inside the height range and past the cursor are visited
+    pub fn list_transactions(
+        &self,
+        filter: &TxFilter,
+        order: SortOrder,
+        cursor: Option<TxPosition>,
+        limit: usize
+    ) -> TxPage {
+        let mut low = filter.from_height.unwrap_or(0);
+        let mut high = filter.to_height.unwrap_or(self.height()).min(self.height());
+        match (order, cursor) {
+            (SortOrder::Asc, Some(cursor)) => {
+                low = low.max(cursor.height);
+            }
+            (SortOrder::Desc, Some(cursor)) => {
+                high = high.min(cursor.height);
+            }
+            (_, None) => {}
+        }
+        let blocks = self.chain.get(low..=high).unwrap_or_default();
+        let positions = blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(offset, block)| {
+                let height = low + offset;
+                block.transactions
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, tx)| (TxPosition { height, index }, tx))
+            });
+        let after_cursor = |position: &TxPosition| {
+            match (order, cursor) {
+                (_, None) => true,
+                (SortOrder::Asc, Some(cursor)) => *position > cursor,
+                (SortOrder::Desc, Some(cursor)) => *position < cursor,
+            }
+        };
+        let ordered: Box<dyn Iterator<Item = (TxPosition, &Transaction)>> = match order {
+            SortOrder::Asc => Box::new(positions),
+            SortOrder::Desc => Box::new(positions.rev()),
+        };
+        let mut transactions: Vec<(TxPosition, Transaction)> = ordered
+            .filter(|(position, tx)| after_cursor(position) && filter.matches(tx))
+            .take(limit + 1)
+            .map(|(position, tx)| (position, tx.clone()))
+            .collect();
+        let next_cursor = match transactions.len() > limit {
+            true => {
+                transactions.truncate(limit);
+                transactions.last().map(|(position, _)| *position)
+            }
+            false => None,
+        };
+        TxPage { transactions, next_cursor }
+    }
+
+    // Transactions sent from or to an address, with the height of their block
+    pub fn transactions_for(&self, address: &Address) -> Vec<(TxPosition, Transaction)> {
+        self.chain
+            .iter()
+            .enumerate()
+            .flat_map(|(height, block)| {
+                block.transactions
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, txn)| (TxPosition { height, index }, txn))
+            })
+            .filter(|(_, txn)| &txn.from_address == address || &txn.to_address == address)
+            .map(|(position, txn)| (position, txn.clone()))
+            .collect()
+    }
+
+    pub fn chain_info(&self) -> ChainInfo {
+        ChainInfo {
+            chain_id: self.genesis.chain_id.clone(),
+            genesis_hash: self.genesis_hash(),
+            height: self.height(),
+            tip_hash: self.get_latest_block().map(|block| block.hash.clone()).unwrap_or_default(),
+            difficulty: self.difficulty,
+            block_capacity: self.genesis.block_capacity,
+            mining_reward: self.mining_reward,
+            pending_transactions: self.pending_transactions.len(),
+        }
     }
+
+    pub fn total_supply(&self) -> f64 {
+        self.accounts.balances.values().sum()
+    }
+
+    // Mean seconds between the last `window` mined blocks, genesis excluded
+    pub fn average_block_time(&self, window: usize) -> Option<f64> {
+        let start = self.chain.len().saturating_sub(window + 1).max(1);
+        let recent = &self.chain[start..];
+        let span = recent.last()?.timestamp.saturating_sub(recent.first()?.timestamp);
+        if recent.len() < 2 || span == 0 {
+            return None;
+        }
+        Some((span as f64) / ((recent.len() - 1) as f64))
+    }
+
+    // Hashes per second the network needs to produce blocks at the recent pace: each
+    // leading zero hex digit of difficulty multiplies the expected work by 16
+    pub fn network_hashrate(&self, window: usize) -> Option<f64> {
+        let block_time = self.average_block_time(window)?;
+        Some((16f64).powi(self.difficulty as i32) / block_time)
+    }
+
+    pub fn block_by_hash(&self, hash: &str) -> Option<(usize, &Block)> {
+        self.chain
+            .iter()
+            .enumerate()
+            .find(|(_, block)| block.hash == hash)
+    }
+
+    // Mined transaction with the height of its block, looked up by its id
+    pub fn find_transaction(&self, id: &str) -> Option<(TxPosition, &Transaction)> {
+        self.chain
+            .iter()
+            .enumerate()
+            .find_map(|(height, block)| {
+                let index = block.transactions.iter().position(|txn| txn.id == id)?;
+                Some((TxPosition { height, index }, &block.transactions[index]))
+            })
+    }
+
+    // Transfers carry no fee in this protocol, so the estimate is always zero
+    pub fn estimate_fee(&self) -> f64 {
+        0.0
+    }
+
+    pub fn get_balance(&self, address: &Address) -> f64 {
+        self.accounts.get_balance(address)
+    }
+
+    // Account state with the mempool applied on top, as the next block would see it
+    pub fn pending_accounts(&self) -> Account {
+        let mut accounts = self.accounts.clone();
+        for txn in &self.pending_transactions {
+            accounts.apply(txn);
+        }
+        accounts
+    }
+
+    // Nonce the next transaction from this address must carry, counting the mempool
+    pub fn next_nonce(&self, address: &Address) -> u32 {
+        self.pending_accounts().get_nonce(address)
+    }
+}
+
+fn default_mempool_capacity() -> usize {
+    DEFAULT_MEMPOOL_CAPACITY
+}
+
+// Count and log a refused transaction, passing the error on to the caller
+fn reject_transaction(reason: &str, error: anyhow::Error) -> anyhow::Error {
+    METRICS.reject(reason);
+    debug!(reason, "Rejected transaction: {}", error);
+    error
 }
@@ -0,0 +1,450 @@
+use anyhow::Context;
+use clap::{ Parser, Subcommand };
+use secp256k1::SecretKey;
+use serde::{ Deserialize, Serialize };
+use std::{ collections::BTreeMap, fs, net::SocketAddr, path::{ Path, PathBuf }, str::FromStr };
+
+use crate::address::Address;
+use crate::auth::{ ApiKeyConfig, Role };
+use crate::blockchain::DEFAULT_MEMPOOL_CAPACITY;
+use crate::genesis::GenesisSpec;
+use crate::telemetry::{ Secret, LOG_FORMATS };
+use crate::wallet::Wallet;
+
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+
+// Command-line flags; each one can also come from the environment (or .env)
+// and applies to every subcommand
+#[derive(Parser, Debug, Default)]
+#[command(name = "node", about = "Duma proof-of-work node")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<NodeCommand>,
+    /// Path to a TOML config file
+    #[arg(long, global = true, env = "NODE_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address the HTTP API listens on
+    #[arg(long, global = true, env = "HTTP_LISTEN")]
+    pub http_listen: Option<SocketAddr>,
+    /// Address the P2P server listens on
+    #[arg(long, global = true, env = "P2P_LISTEN")]
+    pub p2p_listen: Option<SocketAddr>,
+    /// Host allowed by CORS, combined with the HTTP port
+    #[arg(long, global = true, env = "DOMAIN")]
+    pub domain: Option<String>,
+    /// Comma separated seed peers, e.g. 127.0.0.1:7101,127.0.0.1:7201
+    #[arg(long, global = true, env = "PEERS", value_delimiter = ',')]
+    pub peers: Option<Vec<SocketAddr>>,
+    #[arg(long, global = true, env = "GENESIS_FILE")]
+    pub genesis_file: Option<PathBuf>,
+    #[arg(long, global = true, env = "DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Override the genesis difficulty (leading zero hex digits)
+    #[arg(long, global = true, env = "DIFFICULTY")]
+    pub difficulty: Option<usize>,
+    /// Override the genesis block capacity
+    #[arg(long, global = true, env = "BLOCK_CAPACITY")]
+    pub block_capacity: Option<usize>,
+    /// Address that receives mining rewards
+    #[arg(long, global = true, env = "MINER_ADDRESS")]
+    pub miner_address: Option<String>,
+    #[arg(long, global = true, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// Log output, text or json
+    #[arg(long, global = true, env = "LOG_FORMAT")]
+    pub log_format: Option<String>,
+    /// Encrypted keystore holding the node wallet, created on first start if missing;
+    /// data_dir/keystore/node.json by default
+    #[arg(long, global = true, env = "KEYSTORE_FILE")]
+    pub keystore_file: Option<PathBuf>,
+    #[arg(long, global = true, env = "KEYSTORE_PASSWORD", hide_env_values = true)]
+    pub keystore_password: Option<Secret<String>>,
+    /// Secret key of a funded account; enables the faucet on dev networks
+    #[arg(long, global = true, env = "FAUCET_SECRET_KEY", hide_env_values = true)]
+    pub faucet_secret_key: Option<Secret<String>>,
+    /// Coins paid out per faucet request
+    #[arg(long, global = true, env = "FAUCET_AMOUNT")]
+    pub faucet_amount: Option<f64>,
+    /// Seconds an address or client must wait between faucet payouts
+    #[arg(long, global = true, env = "FAUCET_COOLDOWN_SECS")]
+    pub faucet_cooldown_secs: Option<u64>,
+    /// Blocks between account state snapshots, 0 to disable them
+    #[arg(long, global = true, env = "SNAPSHOT_INTERVAL")]
+    pub snapshot_interval: Option<usize>,
+    /// Number of snapshots kept on disk
+    #[arg(long, global = true, env = "SNAPSHOT_KEEP")]
+    pub snapshot_keep: Option<usize>,
+    /// Role of HTTP clients that present no API key
+    #[arg(long, global = true, env = "ANONYMOUS_ROLE", value_enum)]
+    pub anonymous_role: Option<Role>,
+    /// HTTP requests per minute per client on routes without their own quota, 0 for no limit
+    #[arg(long, global = true, env = "RATE_LIMIT_PER_MINUTE")]
+    pub rate_limit_per_minute: Option<u32>,
+    /// Largest HTTP request body accepted, in bytes
+    #[arg(long, global = true, env = "MAX_BODY_BYTES")]
+    pub max_body_bytes: Option<usize>,
+    /// Pending transactions kept for mining; further submissions are refused
+    #[arg(long, global = true, env = "MEMPOOL_CAPACITY")]
+    pub mempool_capacity: Option<usize>,
+}
+
+// Offline commands must not run against a data_dir a running node is writing to
+#[derive(Subcommand, Debug, Clone)]
+pub enum NodeCommand {
+    /// Run the node: P2P networking and the HTTP API (the default)
+    Run,
+    /// Mine blocks locally on top of the stored chain, including empty ones
+    Mine {
+        #[arg(long, default_value_t = 1)]
+        blocks: usize,
+    },
+    /// Replay and fully validate every stored block without starting the node
+    Verify,
+    /// Write a range of the stored chain to a portable archive with a checksum trailer
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+        /// First height to export
+        #[arg(long, default_value_t = 0)]
+        from: usize,
+        /// Last height to export, the tip when omitted
+        #[arg(long)]
+        to: Option<usize>,
+    },
+    /// Validate blocks from an archive and append them to the stored chain
+    Import {
+        file: PathBuf,
+    },
+    /// Rebuild the store from its blocks, dropping everything after the first invalid one
+    Reindex,
+    /// Snapshot account state at the stored tip and prune old snapshots
+    Snapshot,
+    /// Generate an API key and print the entry to add to api_keys in the config
+    ApiKey {
+        /// Label shown in logs for requests made with the key
+        #[arg(long)]
+        name: String,
+        #[arg(long, value_enum, default_value_t = Role::Admin)]
+        role: Role,
+    },
+    /// Create a funded faucet key for a local dev network: the key goes into the env file,
+    /// its allocation into genesis_file
+    DevSetup {
+        /// Genesis balance of the faucet account
+        #[arg(long, default_value_t = 1_000_000.0)]
+        faucet_balance: f64,
+        #[arg(long, default_value = ".env")]
+        env_file: PathBuf,
+    },
+    /// Print the status of a running node
+    Status {
+        /// Base URL of the node HTTP API, http_listen on this host by default
+        #[arg(long)]
+        node: Option<String>,
+        /// API key for nodes that refuse anonymous clients
+        #[arg(long, env = "API_KEY", hide_env_values = true)]
+        api_key: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub http_listen: SocketAddr,
+    pub p2p_listen: SocketAddr,
+    pub domain: String,
+    pub peers: Vec<SocketAddr>,
+    pub genesis_file: PathBuf,
+    pub data_dir: PathBuf,
+    // Consensus overrides; every node on the network must use the same values
+    pub difficulty: Option<usize>,
+    pub block_capacity: Option<usize>,
+    pub miner_address: Option<Address>,
+    pub mempool_capacity: usize,
+    pub log_level: String,
+    pub log_format: String,
+    pub keystore_file: Option<PathBuf>,
+    // Better supplied through the environment than written into the config file
+    pub keystore_password: Option<Secret<String>>,
+    pub faucet_secret_key: Option<Secret<String>>,
+    pub faucet_amount: f64,
+    pub faucet_cooldown_secs: u64,
+    pub snapshot_interval: usize,
+    pub snapshot_keep: usize,
+    pub anonymous_role: Role,
+    pub api_keys: Vec<ApiKeyConfig>,
+    // Quotas are per API key, or per IP for clients without one
+    pub rate_limit_per_minute: u32,
+    // Routes with their own quota, by route pattern such as "/wallet/new"
+    pub route_rate_limits: BTreeMap<String, u32>,
+    pub max_body_bytes: usize,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            http_listen: SocketAddr::from(([0, 0, 0, 0], 7000)),
+            p2p_listen: SocketAddr::from(([0, 0, 0, 0], 7001)),
+            domain: "127.0.0.1".to_string(),
+            peers: vec![],
+            genesis_file: PathBuf::from("genesis.json"),
+            data_dir: PathBuf::from("data"),
+            difficulty: None,
+            block_capacity: None,
+            miner_address: None,
+            mempool_capacity: DEFAULT_MEMPOOL_CAPACITY,
+            log_level: "info".to_string(),
+            log_format: "text".to_string(),
+            keystore_file: None,
+            keystore_password: None,
+            faucet_secret_key: None,
+            faucet_amount: 10.0,
+            faucet_cooldown_secs: 60 * 60,
+            snapshot_interval: 1000,
+            snapshot_keep: 3,
+            anonymous_role: Role::Read,
+            api_keys: vec![],
+            rate_limit_per_minute: 600,
+            // Routes that create keys or mine are far more expensive than reads
+            route_rate_limits: BTreeMap::from([
+                ("/transaction/create".to_string(), 10),
+                ("/transaction/submit".to_string(), 60),
+                ("/wallet/hd/new".to_string(), 10),
+                ("/wallet/hd/restore".to_string(), 10),
+                ("/wallet/new".to_string(), 10),
+            ]),
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl NodeConfig {
+    // Defaults, then the config file, then environment and command-line overrides
+    pub fn load(cli: Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => NodeConfig::from_file(path)?,
+            None => NodeConfig::default(),
+        };
+        config.apply(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs
+            ::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply(&mut self, cli: Cli) -> anyhow::Result<()> {
+        if let Some(http_listen) = cli.http_listen {
+            self.http_listen = http_listen;
+        }
+        if let Some(p2p_listen) = cli.p2p_listen {
+            self.p2p_listen = p2p_listen;
+        }
+        if let Some(domain) = cli.domain {
+            self.domain = domain;
+        }
+        if let Some(peers) = cli.peers {
+            self.peers = peers;
+        }
+        if let Some(genesis_file) = cli.genesis_file {
+            self.genesis_file = genesis_file;
+        }
+        if let Some(data_dir) = cli.data_dir {
+            self.data_dir = data_dir;
+        }
+        if cli.difficulty.is_some() {
+            self.difficulty = cli.difficulty;
+        }
+        if cli.block_capacity.is_some() {
+            self.block_capacity = cli.block_capacity;
+        }
+        if let Some(miner_address) = cli.miner_address {
+            self.miner_address = match miner_address.as_str() {
+                "" => None,
+                address => Some(address.parse().context("Invalid miner_address")?),
+            };
+        }
+        if let Some(log_level) = cli.log_level {
+            self.log_level = log_level;
+        }
+        if let Some(log_format) = cli.log_format {
+            self.log_format = log_format;
+        }
+        if cli.keystore_file.is_some() {
+            self.keystore_file = cli.keystore_file;
+        }
+        if cli.keystore_password.is_some() {
+            self.keystore_password = cli.keystore_password;
+        }
+        if let Some(faucet_secret_key) = cli.faucet_secret_key {
+            self.faucet_secret_key = Some(faucet_secret_key).filter(|key| !key.expose().is_empty());
+        }
+        if let Some(faucet_amount) = cli.faucet_amount {
+            self.faucet_amount = faucet_amount;
+        }
+        if let Some(faucet_cooldown_secs) = cli.faucet_cooldown_secs {
+            self.faucet_cooldown_secs = faucet_cooldown_secs;
+        }
+        if let Some(snapshot_interval) = cli.snapshot_interval {
+            self.snapshot_interval = snapshot_interval;
+        }
+        if let Some(snapshot_keep) = cli.snapshot_keep {
+            self.snapshot_keep = snapshot_keep;
+        }
+        if let Some(anonymous_role) = cli.anonymous_role {
+            self.anonymous_role = anonymous_role;
+        }
+        if let Some(rate_limit_per_minute) = cli.rate_limit_per_minute {
+            self.rate_limit_per_minute = rate_limit_per_minute;
+        }
+        if let Some(max_body_bytes) = cli.max_body_bytes {
+            self.max_body_bytes = max_body_bytes;
+        }
+        if let Some(mempool_capacity) = cli.mempool_capacity {
+            self.mempool_capacity = mempool_capacity;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.http_listen == self.p2p_listen {
+            return Err(anyhow::anyhow!("http_listen and p2p_listen are both {}", self.http_listen));
+        }
+        if self.peers.contains(&self.p2p_listen) {
+            return Err(anyhow::anyhow!("peers must not contain our own p2p_listen address"));
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            return Err(
+                anyhow::anyhow!(
+                    "log_level '{}' must be one of {}",
+                    self.log_level,
+                    LOG_LEVELS.join(", ")
+                )
+            );
+        }
+        if !LOG_FORMATS.contains(&self.log_format.as_str()) {
+            return Err(
+                anyhow::anyhow!(
+                    "log_format '{}' must be one of {}",
+                    self.log_format,
+                    LOG_FORMATS.join(", ")
+                )
+            );
+        }
+        if let Some(difficulty) = self.difficulty {
+            if difficulty == 0 || difficulty > 64 {
+                return Err(anyhow::anyhow!("difficulty must be between 1 and 64, got {}", difficulty));
+            }
+        }
+        if self.block_capacity == Some(0) {
+            return Err(anyhow::Error::msg("block_capacity must be at least 1"));
+        }
+        let password = self.keystore_password.as_ref().map(|password| password.expose().as_str());
+        if self.keystore_file.is_some() && password.unwrap_or("").is_empty() {
+            return Err(anyhow::Error::msg("keystore_file is set but keystore_password is empty"));
+        }
+        if password == Some("") {
+            return Err(anyhow::Error::msg("keystore_password is empty"));
+        }
+        if let Some(secret_key) = &self.faucet_secret_key {
+            SecretKey::from_str(secret_key.expose()).map_err(|_|
+                anyhow::Error::msg("faucet_secret_key is not a valid secret key")
+            )?;
+        }
+        if !(self.faucet_amount > 0.0 && self.faucet_amount.is_finite()) {
+            return Err(anyhow::anyhow!("faucet_amount must be positive, got {}", self.faucet_amount));
+        }
+        if self.snapshot_keep == 0 {
+            return Err(anyhow::Error::msg("snapshot_keep must be at least 1"));
+        }
+        // Admin routes spend from the node wallet, so they always need a key
+        if self.anonymous_role == Role::Admin {
+            return Err(anyhow::Error::msg("anonymous_role must not be admin; give admins an API key instead"));
+        }
+        for (i, api_key) in self.api_keys.iter().enumerate() {
+            if api_key.key_hash.len() != 64 || hex::decode(&api_key.key_hash).is_err() {
+                return Err(anyhow::anyhow!("api_keys '{}' key_hash must be a hex SHA-256", api_key.name));
+            }
+            if api_key.role == Role::None {
+                return Err(anyhow::anyhow!("api_keys '{}' role must be read, submit or admin", api_key.name));
+            }
+            if self.api_keys[..i].iter().any(|other| other.name == api_key.name) {
+                return Err(anyhow::anyhow!("api_keys name '{}' is used twice", api_key.name));
+            }
+        }
+        if let Some(route) = self.route_rate_limits.keys().find(|route| !route.starts_with('/')) {
+            return Err(anyhow::anyhow!("route_rate_limits route '{}' must start with /", route));
+        }
+        if self.max_body_bytes == 0 {
+            return Err(anyhow::Error::msg("max_body_bytes must be at least 1"));
+        }
+        if self.mempool_capacity == 0 {
+            return Err(anyhow::Error::msg("mempool_capacity must be at least 1"));
+        }
+        if !self.genesis_file.is_file() {
+            return Err(anyhow::anyhow!("genesis_file {} does not exist", self.genesis_file.display()));
+        }
+        fs
+            ::create_dir_all(&self.data_dir)
+            .with_context(|| format!("Cannot create data_dir {}", self.data_dir.display()))?;
+        Ok(())
+    }
+
+    // Genesis spec with any consensus overrides from the config applied
+    pub fn genesis(&self) -> anyhow::Result<GenesisSpec> {
+        let mut genesis = GenesisSpec::load(&self.genesis_file)?;
+        if let Some(difficulty) = self.difficulty {
+            genesis.initial_difficulty = difficulty;
+        }
+        if let Some(block_capacity) = self.block_capacity {
+            genesis.block_capacity = block_capacity;
+        }
+        if self.faucet_secret_key.is_some() && !genesis.dev_network {
+            return Err(
+                anyhow::anyhow!("faucet_secret_key is set but chain {} is not a dev_network", genesis.chain_id)
+            );
+        }
+        Ok(genesis)
+    }
+
+    // keystore_file, or data_dir/keystore/node.json when it is not set
+    pub fn keystore_path(&self) -> PathBuf {
+        self.keystore_file.clone().unwrap_or_else(|| self.data_dir.join("keystore").join("node.json"))
+    }
+
+    // Node wallet from its keystore, created on first start and reused after. Without a password
+    // there is nothing to encrypt it with, so mining then needs a miner_address to pay instead of
+    // a key that would be lost on restart
+    pub fn wallet(&self) -> anyhow::Result<Wallet> {
+        let password = match &self.keystore_password {
+            Some(password) => password,
+            None if self.miner_address.is_some() => {
+                return Ok(Wallet::new());
+            }
+            None => {
+                return Err(
+                    anyhow::Error::msg(
+                        "Mining needs a node keystore: set KEYSTORE_PASSWORD (the keystore is created under \
+                         data_dir/keystore on first start) or a miner_address"
+                    )
+                );
+            }
+        };
+        let path = self.keystore_path();
+        if path.exists() {
+            Wallet::unlock(&path, password.expose()).with_context(|| format!("Failed to unlock {}", path.display()))
+        } else {
+            let wallet = Wallet::create_keystore(&path, password.expose())?;
+            tracing::info!("Created node keystore {}", path.display());
+            Ok(wallet)
+        }
+    }
+
+    pub fn log_level(&self) -> tracing::Level {
+        tracing::Level::from_str(&self.log_level).unwrap_or(tracing::Level::INFO)
+    }
+}
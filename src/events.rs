@@ -0,0 +1,184 @@
+use crate::address::Address;
+use crate::api::ApiError;
+use crate::blockchain::{ Blockchain, ChainEvent };
+use crate::extract::Query;
+use crate::metrics::BlockchainLock;
+use crate::transaction::{ Transaction, TxStatus };
+use axum::{
+    extract::State,
+    response::sse::{ Event, KeepAlive, Sse },
+    routing::get,
+    Router,
+};
+use futures_util::stream::{ self, Stream, StreamExt };
+use serde::Deserialize;
+use std::{ collections::HashSet, convert::Infallible, sync::Arc };
+use tokio::sync::{ broadcast, Mutex };
+use utoipa::{ IntoParams, OpenApi };
+
+// Event names sent on the stream; `lagged` is always sent and means the client missed
+// events and should refetch what it shows. `mempool` fires for every transaction the node
+// accepts, however it arrived, and again for transactions a reorg puts back
+pub const EVENT_KINDS: [&str; 5] = ["block", "reorg", "mempool", "tx_confirmed", "tx_failed"];
+
+pub fn event_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
+    Router::new().route("/events", get(stream_events)).with_state(app_state)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(stream_events), tags((name = "events")))]
+pub struct EventsApi;
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EventQuery {
+    /// Comma separated; transaction events are limited to these senders and recipients
+    addresses: Option<String>,
+    /// Comma separated subset of block, reorg, mempool, tx_confirmed and tx_failed, all of
+    /// them when omitted
+    events: Option<String>,
+}
+
+// What one client asked to receive
+struct Subscription {
+    addresses: HashSet<Address>,
+    kinds: HashSet<String>,
+}
+
+impl Subscription {
+    fn parse(query: EventQuery) -> anyhow::Result<Self> {
+        let addresses = split(&query.addresses)
+            .map(|address| address.parse())
+            .collect::<anyhow::Result<HashSet<Address>>>()?;
+        let kinds: HashSet<String> = match &query.events {
+            Some(_) => split(&query.events).map(str::to_string).collect(),
+            None => EVENT_KINDS.iter().map(|kind| kind.to_string()).collect(),
+        };
+        if let Some(unknown) = kinds.iter().find(|kind| !EVENT_KINDS.contains(&kind.as_str())) {
+            return Err(anyhow::anyhow!("Unknown event '{}', expected one of {}", unknown, EVENT_KINDS.join(", ")));
+        }
+        Ok(Subscription { addresses, kinds })
+    }
+
+    fn wants(&self, kind: &str) -> bool {
+        self.kinds.contains(kind)
+    }
+
+    fn involves(&self, tx: &Transaction) -> bool {
+        self.addresses.is_empty() ||
+            self.addresses.contains(&tx.from_address) ||
+            self.addresses.contains(&tx.to_address)
+    }
+
+    // Stream events for one chain event: a block also yields an event per transaction
+    fn events_for(&self, event: &ChainEvent) -> Vec<Event> {
+        let mut events = vec![];
+        match event {
+            ChainEvent::BlockConnected { height, block } => {
+                if self.wants("block") {
+                    events.push(
+                        json_event(
+                            "block",
+                            serde_json::json!({
+                            "height": height,
+                            "hash": block.hash,
+                            "previous_hash": block.previous_hash,
+                            "timestamp": block.timestamp,
+                            "miner": block.miner,
+                            "transactions": block.transactions.len(),
+                            "state_root": block.state_root,
+                        })
+                        )
+                    );
+                }
+                for tx in block.transactions.iter().filter(|tx| self.involves(tx)) {
+                    let kind = if tx.status == TxStatus::SUCCESS { "tx_confirmed" } else { "tx_failed" };
+                    if self.wants(kind) {
+                        events.push(
+                            json_event(
+                                kind,
+                                serde_json::json!({
+                                "height": height,
+                                "block_hash": block.hash,
+                                "tx": tx,
+                            })
+                            )
+                        );
+                    }
+                }
+            }
+            ChainEvent::Reorg { fork_height, disconnected } => {
+                if self.wants("reorg") {
+                    let hashes: Vec<&String> = disconnected
+                        .iter()
+                        .map(|block| &block.hash)
+                        .collect();
+                    events.push(
+                        json_event(
+                            "reorg",
+                            serde_json::json!({
+                            "fork_height": fork_height,
+                            "disconnected": hashes,
+                        })
+                        )
+                    );
+                }
+            }
+            ChainEvent::TransactionAccepted(tx) => {
+                if self.wants("mempool") && self.involves(tx) {
+                    events.push(json_event("mempool", serde_json::json!({ "tx": tx })));
+                }
+            }
+        }
+        events
+    }
+}
+
+fn split(list: &Option<String>) -> impl Iterator<Item = &str> {
+    list.as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn json_event(kind: &str, data: serde_json::Value) -> Event {
+    Event::default().event(kind).data(data.to_string())
+}
+
+// Server-sent events, e.g. GET /events?addresses=duma1...&events=tx_confirmed,tx_failed
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(EventQuery),
+    responses(
+        (
+            status = 200,
+            description = "Stream of JSON events named by kind",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = 400, body = ApiError)
+    )
+)]
+async fn stream_events(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Query(query): Query<EventQuery>
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let subscription = Subscription::parse(query).map_err(ApiError::bad_request)?;
+    let receiver = data.lock_timed().await.subscribe();
+    let events = stream::unfold((receiver, subscription), |(mut receiver, subscription)| async move {
+        let events = match receiver.recv().await {
+            Ok(event) => subscription.events_for(&event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                vec![json_event("lagged", serde_json::json!({ "skipped": skipped }))]
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                return None;
+            }
+        };
+        Some((stream::iter(events.into_iter().map(Ok)), (receiver, subscription)))
+    }).flatten();
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
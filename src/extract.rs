@@ -0,0 +1,54 @@
+use crate::api::ApiError;
+use axum::{
+    async_trait,
+    extract::{ FromRequest, FromRequestParts, Request },
+    http::request::Parts,
+};
+use serde::de::DeserializeOwned;
+
+// Drop-in replacements for axum's extractors that report bad input as an ApiError, so a
+// malformed body, query or path gets the same JSON envelope as every other failure
+
+#[derive(Default)]
+pub struct Json<T>(pub T);
+
+pub struct Query<T>(pub T);
+
+pub struct Path<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T> where T: DeserializeOwned, S: Send + Sync {
+    type Rejection = ApiError;
+
+    // Also covers bodies over the size limit and a missing JSON content type
+    async fn from_request(request: Request, state: &S) -> Result<Self, ApiError> {
+        match axum::Json::<T>::from_request(request, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(ApiError::new(rejection.status(), rejection.body_text())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T> where T: DeserializeOwned, S: Send + Sync {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        match axum::extract::Query::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Query(value)) => Ok(Query(value)),
+            Err(rejection) => Err(ApiError::new(rejection.status(), rejection.body_text())),
+        }
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Path<T> where T: DeserializeOwned + Send, S: Send + Sync {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(rejection) => Err(ApiError::new(rejection.status(), rejection.body_text())),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use secp256k1::{ PublicKey, Secp256k1, SecretKey };
+use std::{ collections::HashMap, net::IpAddr, time::{ Duration, Instant } };
+
+use crate::{ address::Address, blockchain::Blockchain, transaction::Transaction };
+
+// Why a faucet request was refused
+pub enum FaucetError {
+    // Seconds until the address or client may ask again
+    TooSoon(u64),
+    Rejected(anyhow::Error),
+}
+
+// Development faucet paying out of a funded genesis account with ordinary signed transfers
+pub struct Faucet {
+    secret_key: SecretKey,
+    amount: f64,
+    cooldown: Duration,
+    // Last payout per recipient address and per client IP
+    last_payout: HashMap<String, Instant>,
+}
+
+impl Faucet {
+    pub fn new(secret_key: SecretKey, amount: f64, cooldown: Duration) -> Self {
+        Faucet {
+            secret_key,
+            amount,
+            cooldown,
+            last_payout: HashMap::new(),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&PublicKey::from_secret_key(&Secp256k1::new(), &self.secret_key))
+    }
+
+    pub fn payout(
+        &mut self,
+        blockchain: &mut Blockchain,
+        to_address: Address,
+        client: IpAddr
+    ) -> Result<Transaction, FaucetError> {
+        let cooldown = self.cooldown;
+        self.last_payout.retain(|_, paid_at| paid_at.elapsed() < cooldown);
+        let keys = [to_address.to_string(), client.to_string()];
+        if let Some(wait) = keys
+            .iter()
+            .filter_map(|key| self.last_payout.get(key))
+            .map(|paid_at| cooldown.saturating_sub(paid_at.elapsed()))
+            .max() {
+            return Err(FaucetError::TooSoon(wait.as_secs().max(1)));
+        }
+        if to_address == self.address() {
+            return Err(FaucetError::Rejected(anyhow::Error::msg("Cannot pay the faucet itself")));
+        }
+
+        if blockchain.get_balance(&self.address()) < self.amount {
+            return Err(FaucetError::Rejected(anyhow::Error::msg("Faucet is out of funds")));
+        }
+
+        let nonce = blockchain.next_nonce(&self.address());
+        let genesis_hash = blockchain.genesis_hash();
+        let transaction = Transaction::new_signed(&self.secret_key, &genesis_hash, to_address, self.amount, nonce);
+        let tx = blockchain.submit_transaction(transaction).map_err(FaucetError::Rejected)?;
+        let now = Instant::now();
+        for key in keys {
+            self.last_payout.insert(key, now);
+        }
+        Ok(tx)
+    }
+}
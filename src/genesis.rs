@@ -0,0 +1,75 @@
+use serde::{ Deserialize, Serialize };
+use sha256::digest;
+use std::{ collections::BTreeMap, fs, path::Path };
+use anyhow::Context;
+
+use crate::address::Address;
+
+// Everything every node must agree on before the first block is mined
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GenesisSpec {
+    pub chain_id: String,
+    pub timestamp: u64,
+    // Number of leading zero hex digits a block hash needs
+    pub initial_difficulty: usize,
+    pub block_capacity: usize,
+    pub mining_reward: f64,
+    // Development networks may run a faucet; never set this for a public network
+    #[serde(default)]
+    pub dev_network: bool,
+    // Starting balances, kept sorted so every node derives the same state
+    pub allocations: BTreeMap<Address, f64>,
+}
+
+impl GenesisSpec {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs
+            ::read_to_string(path)
+            .with_context(|| format!("Failed to read genesis file {}", path.display()))?;
+        let spec: GenesisSpec = serde_json
+            ::from_str(&contents)
+            .with_context(|| format!("Invalid genesis file {}", path.display()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents + "\n").with_context(|| format!("Failed to write genesis file {}", path.display()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.chain_id.is_empty() {
+            return Err(anyhow::Error::msg("Genesis chain_id must not be empty"));
+        }
+        if self.block_capacity == 0 {
+            return Err(anyhow::Error::msg("Genesis block_capacity must be at least 1"));
+        }
+        // Same bounds as the difficulty setting in the node config
+        if self.initial_difficulty == 0 || self.initial_difficulty > 64 {
+            return Err(
+                anyhow::anyhow!("Genesis initial_difficulty must be between 1 and 64, got {}", self.initial_difficulty)
+            );
+        }
+        if !self.mining_reward.is_finite() || self.mining_reward < 0.0 {
+            return Err(
+                anyhow::anyhow!("Genesis mining_reward must be finite and non-negative, got {}", self.mining_reward)
+            );
+        }
+        // NaN would slip through every balance comparison later on
+        let invalid = self.allocations.iter().find(|(_, balance)| !balance.is_finite() || **balance < 0.0);
+        if let Some((address, balance)) = invalid {
+            return Err(
+                anyhow::anyhow!("Genesis allocation for {} must be finite and non-negative, got {}", address, balance)
+            );
+        }
+        Ok(())
+    }
+
+    // Hash over the whole spec, so nodes with different allocations get different genesis blocks
+    pub fn commitment(&self) -> String {
+        digest(serde_json::to_string(self).expect("Genesis spec serializes to JSON"))
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{ Deserialize, Serialize };
+use std::{ collections::{ HashMap, HashSet, VecDeque }, fmt, net::SocketAddr, time::Instant };
+use tokio::time::Duration;
+
+// Misbehavior score at which a peer gets banned
+pub const BAN_THRESHOLD: u32 = 100;
+pub const BAN_DURATION: Duration = Duration::from_secs(60 * 60);
+// Most inventory items accepted in a single announcement
+pub const MAX_INVENTORY_PER_MESSAGE: usize = 1000;
+
+// Identifier of an object announced to peers before the object itself is sent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "id")]
+pub enum Inventory {
+    Block(String),
+    Transaction(String),
+}
+
+// Bounded set of recently seen inventory, evicting the oldest entries first
+pub struct SeenSet {
+    capacity: usize,
+    order: VecDeque<Inventory>,
+    items: HashSet<Inventory>,
+}
+
+impl SeenSet {
+    pub fn new(capacity: usize) -> Self {
+        SeenSet {
+            capacity,
+            order: VecDeque::new(),
+            items: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, item: &Inventory) -> bool {
+        self.items.contains(item)
+    }
+
+    // Returns false if the item was already in the set
+    pub fn insert(&mut self, item: Inventory) -> bool {
+        if !self.items.insert(item.clone()) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+// Token bucket pacing how many messages we read from a peer
+pub struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_per_sec: u32) -> Self {
+        RateLimiter {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill_per_sec: refill_per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Take a token, returning how long the caller has to wait before using it. The bucket
+    // may go into debt, so a busy peer is slowed down rather than refused
+    pub fn acquire(&mut self) -> Duration {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.last_refill = Instant::now();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity) - 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.refill_per_sec)
+        }
+    }
+}
+
+// Peer behaviour that counts towards a ban, carried inside an anyhow::Error
+#[derive(Debug)]
+pub struct Misbehavior {
+    pub score: u32,
+    pub reason: String,
+}
+
+impl fmt::Display for Misbehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (+{})", self.reason, self.score)
+    }
+}
+
+impl std::error::Error for Misbehavior {}
+
+pub fn misbehavior(score: u32, reason: &str) -> anyhow::Error {
+    anyhow::Error::new(Misbehavior {
+        score,
+        reason: reason.to_string(),
+    })
+}
+
+// Peers banned until a given instant, keyed by their listen address so other nodes behind
+// the same IP, e.g. on one host or behind NAT, stay reachable
+#[derive(Default)]
+pub struct BanList {
+    banned: HashMap<SocketAddr, Instant>,
+}
+
+impl BanList {
+    pub fn ban(&mut self, peer: SocketAddr) {
+        self.banned.insert(peer, Instant::now() + BAN_DURATION);
+    }
+
+    pub fn is_banned(&mut self, peer: &SocketAddr) -> bool {
+        let now = Instant::now();
+        self.banned.retain(|_, until| *until > now);
+        self.banned.contains_key(peer)
+    }
+}
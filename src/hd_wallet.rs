@@ -0,0 +1,261 @@
+use anyhow::Context;
+use bip39::Mnemonic;
+use ring::hmac;
+use secp256k1::{ PublicKey, Scalar, Secp256k1, SecretKey };
+use std::{ collections::HashMap, fmt, str::FromStr };
+
+use crate::address::Address;
+use crate::wallet::Wallet;
+
+// Account level path; the address index is appended as the last, non-hardened step
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/0'/0'/0";
+// Unused addresses in a row after which restoring stops looking
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+// Largest gap limit a client may ask for; every step is a key derivation
+pub const MAX_GAP_LIMIT: u32 = 100;
+const HARDENED: u32 = 1 << 31;
+
+// BIP32 path such as m/44'/0'/0'/0, hardened steps marked with ' or h
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    pub fn child(&self, index: u32) -> DerivationPath {
+        let mut steps = self.0.clone();
+        steps.push(index);
+        DerivationPath(steps)
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = anyhow::Error;
+
+    fn from_str(path: &str) -> anyhow::Result<Self> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            return Err(anyhow::anyhow!("Derivation path '{}' must start with m", path));
+        }
+        parts
+            .map(|part| {
+                let (number, hardened) = match part.strip_suffix('\'').or_else(|| part.strip_suffix('h')) {
+                    Some(number) => (number, true),
+                    None => (part, false),
+                };
+                let index: u32 = number
+                    .parse()
+                    .ok()
+                    .filter(|index| *index < HARDENED)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid step '{}' in derivation path '{}'", part, path))?;
+                Ok(if hardened { index | HARDENED } else { index })
+            })
+            .collect::<anyhow::Result<Vec<u32>>>()
+            .map(DerivationPath)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for step in &self.0 {
+            if step & HARDENED != 0 {
+                write!(f, "/{}'", step & !HARDENED)?;
+            } else {
+                write!(f, "/{}", step)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Secret key plus chain code, enough to derive every child below it
+#[derive(Clone)]
+pub struct ExtendedKey {
+    pub secret_key: SecretKey,
+    chain_code: [u8; 32],
+}
+
+impl ExtendedKey {
+    pub fn master(seed: &[u8]) -> anyhow::Result<Self> {
+        ExtendedKey::from_hmac(b"Bitcoin seed", seed, None)
+    }
+
+    pub fn derive_child(&self, index: u32) -> anyhow::Result<Self> {
+        let mut data = Vec::with_capacity(37);
+        if index & HARDENED != 0 {
+            data.push(0);
+            data.extend_from_slice(&self.secret_key.secret_bytes());
+        } else {
+            let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &self.secret_key);
+            data.extend_from_slice(&public_key.serialize());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        ExtendedKey::from_hmac(&self.chain_code, &data, Some(&self.secret_key))
+    }
+
+    pub fn derive_path(&self, path: &DerivationPath) -> anyhow::Result<Self> {
+        path.0.iter().try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+
+    // Left half of the HMAC becomes the key (added to the parent's), right half the chain code
+    fn from_hmac(key: &[u8], data: &[u8], parent: Option<&SecretKey>) -> anyhow::Result<Self> {
+        let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, key), data);
+        let (left, right) = tag.as_ref().split_at(32);
+        let mut tweak = [0u8; 32];
+        tweak.copy_from_slice(left);
+        let secret_key = match parent {
+            None => SecretKey::from_slice(&tweak)?,
+            Some(parent) => parent.add_tweak(&Scalar::from_be_bytes(tweak)?)?,
+        };
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(right);
+        Ok(ExtendedKey { secret_key, chain_code })
+    }
+}
+
+// Wallet whose keys all come from one backed-up mnemonic phrase
+pub struct HdWallet {
+    mnemonic: Mnemonic,
+    master: ExtendedKey,
+    path: DerivationPath,
+}
+
+impl HdWallet {
+    // New random mnemonic of 12, 15, 18, 21 or 24 words
+    pub fn generate(word_count: usize, path: DerivationPath) -> anyhow::Result<Self> {
+        let mnemonic = Mnemonic::generate(word_count).map_err(|e| anyhow::anyhow!("Cannot generate mnemonic: {}", e))?;
+        HdWallet::from_mnemonic(mnemonic, "", path)
+    }
+
+    pub fn restore(phrase: &str, passphrase: &str, path: DerivationPath) -> anyhow::Result<Self> {
+        let mnemonic = Mnemonic::parse(phrase).map_err(|e| anyhow::anyhow!("Invalid mnemonic: {}", e))?;
+        HdWallet::from_mnemonic(mnemonic, passphrase, path)
+    }
+
+    fn from_mnemonic(mnemonic: Mnemonic, passphrase: &str, path: DerivationPath) -> anyhow::Result<Self> {
+        let master = ExtendedKey::master(&mnemonic.to_seed(passphrase)).context("Invalid seed")?;
+        Ok(HdWallet { mnemonic, master, path })
+    }
+
+    pub fn mnemonic(&self) -> String {
+        self.mnemonic.to_string()
+    }
+
+    pub fn path(&self) -> &DerivationPath {
+        &self.path
+    }
+
+    // Key at the given address index below the account path
+    pub fn derive(&self, index: u32) -> anyhow::Result<Wallet> {
+        let key = self.master.derive_path(&self.path.child(index))?;
+        Ok(Wallet::from_secret_key(&key.secret_key))
+    }
+
+    // Addresses in index order up to the last one holding funds, stopping after gap_limit unused ones
+    pub fn restore_addresses(
+        &self,
+        balances: &HashMap<Address, f64>,
+        gap_limit: u32
+    ) -> anyhow::Result<Vec<Wallet>> {
+        let mut wallets = Vec::new();
+        let mut used: u32 = 0;
+        let mut index: u32 = 0;
+        while index < used.saturating_add(gap_limit) {
+            let wallet = self.derive(index)?;
+            index += 1;
+            if balances.contains_key(&wallet.address()) {
+                used = index;
+            }
+            wallets.push(wallet);
+        }
+        wallets.truncate(used.max(1) as usize);
+        Ok(wallets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHRASE: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn assert_key(key: &ExtendedKey, secret_key: &str, chain_code: &str) {
+        assert_eq!(hex::encode(key.secret_key.secret_bytes()), secret_key);
+        assert_eq!(hex::encode(key.chain_code), chain_code);
+    }
+
+    // BIP39 reference vector for the all-"abandon" phrase, with and without a passphrase
+    #[test]
+    fn mnemonic_seed_matches_bip39_vector() {
+        let mnemonic = Mnemonic::parse(PHRASE).unwrap();
+        assert_eq!(
+            hex::encode(mnemonic.to_seed("")),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+        let seed = hex::encode(mnemonic.to_seed("TREZOR"));
+        assert_eq!(
+            seed,
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+
+        let wallet = HdWallet::restore(PHRASE, "TREZOR", DEFAULT_DERIVATION_PATH.parse().unwrap()).unwrap();
+        let master = ExtendedKey::master(&hex::decode(seed).unwrap()).unwrap();
+        assert_eq!(wallet.master.secret_key, master.secret_key);
+        assert_eq!(wallet.mnemonic(), PHRASE);
+        assert!(HdWallet::restore("abandon abandon", "", wallet.path().clone()).is_err());
+    }
+
+    // BIP32 test vector 1, covering hardened and normal steps
+    #[test]
+    fn derivation_matches_bip32_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedKey::master(&seed).unwrap();
+        assert_key(
+            &master,
+            "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508"
+        );
+        assert_key(
+            &master.derive_path(&"m/0'".parse().unwrap()).unwrap(),
+            "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+            "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141"
+        );
+        assert_key(
+            &master.derive_path(&"m/0'/1".parse().unwrap()).unwrap(),
+            "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+            "2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19"
+        );
+        assert_key(
+            &master.derive_path(&"m/0h/1/2h".parse().unwrap()).unwrap(),
+            "cbce0d719ecf7431d88e6a89fa1483e02e35092af60c042b1df2ff59fa424dca",
+            "04466b9cc8e161e966409ca52986c584f07e9dc81f735db683c3ff6ec7b1503f"
+        );
+        let deepest = master.derive_path(&"m/0'/1/2'/2/1000000000".parse().unwrap()).unwrap();
+        assert_eq!(
+            hex::encode(deepest.secret_key.secret_bytes()),
+            "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8"
+        );
+    }
+
+    #[test]
+    fn derivation_paths_round_trip() {
+        let path: DerivationPath = "m/44h/0'/0'/0".parse().unwrap();
+        assert_eq!(path.to_string(), "m/44'/0'/0'/0");
+        assert_eq!(path.child(5).to_string(), "m/44'/0'/0'/0/5");
+        assert!("44'/0'".parse::<DerivationPath>().is_err());
+        assert!("m/x".parse::<DerivationPath>().is_err());
+        assert!("m/2147483648".parse::<DerivationPath>().is_err());
+    }
+
+    #[test]
+    fn restore_stops_after_the_gap_limit() {
+        let wallet = HdWallet::restore(PHRASE, "", DEFAULT_DERIVATION_PATH.parse().unwrap()).unwrap();
+        let funded = wallet.derive(3).unwrap().address();
+        let balances = HashMap::from([(funded, 1.0)]);
+        let restored = wallet.restore_addresses(&balances, 5).unwrap();
+        assert_eq!(restored.len(), 4);
+        assert_eq!(restored[3].address(), funded);
+        // Beyond the gap limit the funded address is never reached
+        assert_eq!(wallet.restore_addresses(&balances, 3).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Context;
+use ring::aead::{ Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN };
+use secp256k1::{ PublicKey, Secp256k1, SecretKey };
+use serde::{ Deserialize, Serialize };
+use std::{ fs::{ self, OpenOptions }, io::Write, path::Path };
+use utoipa::ToSchema;
+
+pub const KEYSTORE_VERSION: u32 = 1;
+// scrypt cost: 2^15 rounds with r = 8 needs 32 MiB per unlock
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+// Upper bounds when reading a file, so a crafted keystore cannot demand gigabytes
+const MAX_SCRYPT_LOG_N: u8 = 20;
+const MAX_SCRYPT_R: u32 = 32;
+const MAX_SCRYPT_P: u32 = 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub ciphertext: String,
+    pub nonce: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+}
+
+// Secret key encrypted under a password; the public key is bound in as associated data
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Keystore {
+    pub version: u32,
+    pub public_key: String,
+    pub crypto: KeystoreCrypto,
+}
+
+impl Keystore {
+    pub fn encrypt(secret_key: &SecretKey, password: &str) -> anyhow::Result<Self> {
+        if password.is_empty() {
+            return Err(anyhow::Error::msg("Keystore password must not be empty"));
+        }
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), secret_key);
+        let mut salt = [0u8; 32];
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut salt)?;
+        getrandom::getrandom(&mut nonce)?;
+        let kdfparams = KdfParams {
+            log_n: SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt: hex::encode(salt),
+        };
+
+        let key = derive_key(password, &kdfparams)?;
+        let mut ciphertext = secret_key.secret_bytes().to_vec();
+        key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(public_key.serialize()),
+                &mut ciphertext
+            )
+            .map_err(|_| anyhow::Error::msg("Failed to encrypt secret key"))?;
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            public_key: public_key.to_string(),
+            crypto: KeystoreCrypto {
+                cipher: "chacha20-poly1305".to_string(),
+                ciphertext: hex::encode(ciphertext),
+                nonce: hex::encode(nonce),
+                kdf: "scrypt".to_string(),
+                kdfparams,
+            },
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> anyhow::Result<SecretKey> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(anyhow::anyhow!("Unsupported keystore version {}", self.version));
+        }
+        if self.crypto.cipher != "chacha20-poly1305" || self.crypto.kdf != "scrypt" {
+            return Err(anyhow::anyhow!("Unsupported keystore cipher {} / kdf {}", self.crypto.cipher, self.crypto.kdf));
+        }
+        let public_key: PublicKey = self.public_key.parse().context("Invalid keystore public key")?;
+        let nonce: [u8; NONCE_LEN] = hex
+            ::decode(&self.crypto.nonce)?
+            .try_into()
+            .map_err(|_| anyhow::Error::msg("Invalid keystore nonce"))?;
+        let mut plaintext = hex::decode(&self.crypto.ciphertext).context("Invalid keystore ciphertext")?;
+
+        // A wrong password and a tampered file are indistinguishable here, by design
+        let key = derive_key(password, &self.crypto.kdfparams)?;
+        let secret = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(public_key.serialize()),
+                &mut plaintext
+            )
+            .map_err(|_| anyhow::Error::msg("Wrong password or corrupted keystore"))?;
+        let secret_key = SecretKey::from_slice(secret)?;
+        if PublicKey::from_secret_key(&Secp256k1::new(), &secret_key) != public_key {
+            return Err(anyhow::Error::msg("Keystore secret key does not match its public key"));
+        }
+        Ok(secret_key)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs
+            ::read_to_string(path)
+            .with_context(|| format!("Failed to read keystore {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("Invalid keystore {}", path.display()))
+    }
+
+    // Refuses to overwrite, so an existing key is never lost by accident
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if path.exists() {
+            return Err(anyhow::anyhow!("Keystore {} already exists", path.display()));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("Failed to create keystore {}", path.display()))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+fn derive_key(password: &str, params: &KdfParams) -> anyhow::Result<LessSafeKey> {
+    if params.log_n > MAX_SCRYPT_LOG_N || params.r > MAX_SCRYPT_R || params.p > MAX_SCRYPT_P {
+        return Err(anyhow::Error::msg("Keystore scrypt parameters are too expensive"));
+    }
+    let salt = hex::decode(&params.salt).context("Invalid keystore salt")?;
+    let scrypt_params = scrypt::Params
+        ::new(params.log_n, params.r, params.p, 32)
+        .map_err(|e| anyhow::anyhow!("Invalid scrypt parameters: {}", e))?;
+    let mut key = [0u8; 32];
+    scrypt
+        ::scrypt(password.as_bytes(), &salt, &scrypt_params, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    let key = UnboundKey::new(&CHACHA20_POLY1305, &key).map_err(|_| anyhow::Error::msg("Invalid derived key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_slice(&[7u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn decrypts_with_the_right_password_only() {
+        let keystore = Keystore::encrypt(&secret_key(), "correct horse").unwrap();
+        assert_eq!(keystore.decrypt("correct horse").unwrap(), secret_key());
+        assert!(keystore.decrypt("battery staple").is_err());
+        assert!(Keystore::encrypt(&secret_key(), "").is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_keystores() {
+        let keystore = Keystore::encrypt(&secret_key(), "pw").unwrap();
+
+        let mut other_key = keystore.clone();
+        let other_secret = SecretKey::from_slice(&[8u8; 32]).unwrap();
+        other_key.public_key = PublicKey::from_secret_key(&Secp256k1::new(), &other_secret).to_string();
+        assert!(other_key.decrypt("pw").is_err());
+
+        let mut flipped = keystore.clone();
+        let mut ciphertext = hex::decode(&flipped.crypto.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        flipped.crypto.ciphertext = hex::encode(ciphertext);
+        assert!(flipped.decrypt("pw").is_err());
+
+        let mut expensive = keystore;
+        expensive.crypto.kdfparams.log_n = MAX_SCRYPT_LOG_N + 1;
+        assert!(expensive.decrypt("pw").is_err());
+    }
+
+    #[test]
+    fn saves_and_loads_without_overwriting() {
+        let dir = std::env::temp_dir().join(format!("duma-keystore-test-{}", std::process::id()));
+        let path = dir.join("key.json");
+        let _ = fs::remove_dir_all(&dir);
+
+        let keystore = Keystore::encrypt(&secret_key(), "pw").unwrap();
+        keystore.save(&path).unwrap();
+        assert_eq!(Keystore::load(&path).unwrap().decrypt("pw").unwrap(), secret_key());
+        assert!(Keystore::encrypt(&secret_key(), "pw").unwrap().save(&path).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+pub mod account;
+pub mod address;
+pub mod wallet;
+pub mod block;
+pub mod blockchain;
+pub mod miner;
+pub mod transaction;
+pub mod route;
+pub mod api;
+pub mod extract;
+pub mod auth;
+pub mod rate_limit;
+pub mod p2p;
+pub mod merkle;
+pub mod sync;
+pub mod gossip;
+pub mod genesis;
+pub mod config;
+pub mod storage;
+pub mod archive;
+pub mod snapshot;
+pub mod light_client;
+pub mod events;
+pub mod rpc;
+pub mod metrics;
+pub mod telemetry;
+pub mod faucet;
+pub mod hd_wallet;
+pub mod keystore;
@@ -0,0 +1,171 @@
+use crate::api::{ ApiResponse, Headers, TransactionProof };
+use crate::auth;
+use crate::block::BlockHeader;
+use crate::blockchain::Blockchain;
+use crate::genesis::GenesisSpec;
+use crate::merkle::verify_proof;
+use crate::route::MAX_HEADERS_PER_REQUEST;
+use crate::transaction::{ Transaction, TxStatus };
+use anyhow::Context;
+use serde::{ de::DeserializeOwned, Serialize };
+
+// Follows the chain by headers alone and checks transactions against them with Merkle
+// proofs from a full node, so the node never has to be trusted for more than availability
+pub struct LightClient {
+    node: String,
+    // Sent with every request when the node requires one
+    api_key: Option<String>,
+    difficulty: usize,
+    // Validated headers, genesis first
+    headers: Vec<BlockHeader>,
+}
+
+// A transaction proven to be in the header chain
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedTransaction {
+    pub tx: Transaction,
+    pub height: usize,
+    pub block_hash: String,
+    // 1 when the block is our tip
+    pub confirmations: usize,
+}
+
+impl LightClient {
+    // Headers start from the genesis block built from the spec, so a node on another chain is
+    // rejected on the first sync
+    pub fn new(node: &str, genesis: &GenesisSpec) -> Self {
+        LightClient {
+            node: node.trim_end_matches('/').to_string(),
+            api_key: None,
+            difficulty: genesis.initial_difficulty,
+            headers: vec![Blockchain::create_genesis_block(genesis).header()],
+        }
+    }
+
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    pub fn height(&self) -> usize {
+        self.headers.len() - 1
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        &self.headers[self.height()]
+    }
+
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    // Download and validate new headers, switching to the node's chain only when it is
+    // longer than ours. Returns the number of headers that changed
+    pub fn sync(&mut self) -> anyhow::Result<usize> {
+        let fork = self.find_fork()?;
+        let mut candidate = self.headers[..=fork].to_vec();
+        loop {
+            let batch = self.fetch_headers(candidate.len(), MAX_HEADERS_PER_REQUEST)?;
+            if batch.is_empty() {
+                break;
+            }
+            let parent = &candidate[candidate.len() - 1];
+            BlockHeader::validate_chain(&parent.hash, &batch, self.difficulty).with_context(||
+                format!("Node sent invalid headers after height {}", candidate.len() - 1)
+            )?;
+            let full = batch.len() == MAX_HEADERS_PER_REQUEST;
+            candidate.extend(batch);
+            if !full {
+                break;
+            }
+        }
+        if candidate.len() <= self.headers.len() {
+            return Ok(0);
+        }
+        let changed = candidate.len() - fork - 1;
+        self.headers = candidate;
+        Ok(changed)
+    }
+
+    // Fetch and verify the inclusion proof of a transaction, identified by its id as in
+    // GET /transaction/:hash. None if the node does not know it or it is in a block we have
+    // not synced
+    pub fn verify_transaction(&self, txid: &str) -> anyhow::Result<Option<VerifiedTransaction>> {
+        let proof: TransactionProof = match self.get(&format!("/transaction/{}/proof", txid))? {
+            Some(proof) => proof,
+            None => {
+                return Ok(None);
+            }
+        };
+        let header = match self.headers.get(proof.height) {
+            Some(header) if header.hash == proof.block_hash => header,
+            Some(_) => {
+                return Err(anyhow::anyhow!("Node placed {} in a block that is not in our chain", txid));
+            }
+            None => {
+                return Ok(None);
+            }
+        };
+        if proof.tx.id != txid || !proof.tx.verify_signature() {
+            return Err(anyhow::anyhow!("Node returned a different or unsigned transaction for {}", txid));
+        }
+        if !verify_proof(&proof.tx.hash(), &proof.proof, &header.merkle_root) {
+            return Err(anyhow::anyhow!("Inclusion proof for {} does not match block {}", txid, header.hash));
+        }
+        Ok(
+            Some(VerifiedTransaction {
+                confirmations: self.height() - proof.height + 1,
+                tx: proof.tx,
+                height: proof.height,
+                block_hash: proof.block_hash,
+            })
+        )
+    }
+
+    // Sync, then check that the transaction succeeded in a block with at least
+    // `confirmations` blocks on top of it, counting its own
+    pub fn is_confirmed(&mut self, txid: &str, confirmations: usize) -> anyhow::Result<bool> {
+        self.sync()?;
+        Ok(match self.verify_transaction(txid)? {
+            Some(verified) => verified.tx.status == TxStatus::SUCCESS && verified.confirmations >= confirmations,
+            None => false,
+        })
+    }
+
+    // Highest height where the node's chain still matches ours, probing back from our tip in
+    // growing steps
+    fn find_fork(&self) -> anyhow::Result<usize> {
+        let mut height = self.height();
+        let mut step = 1;
+        loop {
+            let theirs = self.fetch_headers(height, 1)?;
+            if theirs.first().map(|header| &header.hash) == Some(&self.headers[height].hash) {
+                return Ok(height);
+            }
+            if height == 0 {
+                return Err(anyhow::Error::msg("Node is on a chain with a different genesis"));
+            }
+            height = height.saturating_sub(step);
+            step *= 2;
+        }
+    }
+
+    fn fetch_headers(&self, from: usize, limit: usize) -> anyhow::Result<Vec<BlockHeader>> {
+        let response: Option<Headers> = self.get(&format!("/headers?from={}&limit={}", from, limit))?;
+        Ok(response.map(|response| response.headers).unwrap_or_default())
+    }
+
+    // Data of a successful response, None when the node answers 404
+    fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<Option<T>> {
+        let request = ureq::get(&format!("{}{}", self.node, path));
+        match auth::with_api_key(request, self.api_key.as_deref()).call() {
+            Ok(response) => {
+                let envelope: ApiResponse<T> = response.into_json().context("Unexpected response from node")?;
+                Ok(Some(envelope.data))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(ureq::Error::Status(status, _)) => Err(anyhow::anyhow!("Node returned {} for {}", status, path)),
+            Err(e) => Err(anyhow::Error::new(e).context("Cannot reach node")),
+        }
+    }
+}
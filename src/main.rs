@@ -1,73 +1,154 @@
-pub mod account;
-pub mod wallet;
-pub mod block;
-pub mod blockchain;
-pub mod transaction;
-pub mod route;
-use crate::blockchain::Blockchain;
-use crate::transaction::Transaction;
-use crate::account::Account;
-use crate::wallet::Wallet;
-use sha256::digest;
+use advanced_db_blockchain::{
+    archive,
+    auth::{ self, Auth, Role },
+    rate_limit::{ self, RateLimiter },
+    blockchain::Blockchain,
+    config::{ Cli, NodeCommand, NodeConfig },
+    genesis::GenesisSpec,
+    events,
+    metrics,
+    miner,
+    snapshot::{ self, Snapshot, SnapshotStore },
+    storage::{ self, ChainStore },
+    telemetry,
+    wallet::Wallet,
+    faucet,
+    p2p,
+    route,
+    rpc,
+};
+use anyhow::Context;
+use clap::Parser;
 use tower_http::cors::CorsLayer;
 use axum::{
+    extract::DefaultBodyLimit,
     http::{ header::{ ACCEPT, AUTHORIZATION, CONTENT_TYPE }, HeaderValue, Method },
+    middleware,
     routing::get,
     Router,
 };
 use tokio::{ sync::Mutex, task };
-use std::{ net::SocketAddr, sync::Arc };
-use lazy_static::lazy_static;
+use secp256k1::SecretKey;
+use std::{ fs, net::SocketAddr, path::Path, str::FromStr, sync::Arc, time::{ Duration, Instant } };
 
-struct AppState {
-    app_state: Arc<Mutex<Blockchain>>,
-}
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    let mut cli = Cli::parse();
+    let command = cli.command.take().unwrap_or(NodeCommand::Run);
+    let config = NodeConfig::load(cli)?;
+    telemetry::init(config.log_level(), &config.log_format);
 
-lazy_static! {
-    pub static ref GLOBAL_BLOCKCHAIN: Mutex<Blockchain> = Mutex::new(Blockchain {
-        chain: vec![Blockchain::create_genesis_block()],
-        difficulty: 10,
-        pending_transactions: vec![],
-        mining_reward: 50.0,
-        accounts: Account::new(),
-        wallet: Wallet::new(),
-    });
+    match command {
+        NodeCommand::Run => run(config).await,
+        NodeCommand::Mine { blocks } => mine(&config, blocks),
+        NodeCommand::Verify => verify(&config),
+        NodeCommand::Export { out, from, to } => export(&config, &out, from, to),
+        NodeCommand::Import { file } => import(&config, &file),
+        NodeCommand::Reindex => reindex(&config),
+        NodeCommand::Snapshot => take_snapshot(&config),
+        NodeCommand::Status { node, api_key } => status(&config, node, api_key.as_deref()),
+        NodeCommand::ApiKey { name, role } => api_key(&name, role),
+        NodeCommand::DevSetup { faucet_balance, env_file } => dev_setup(&config, faucet_balance, &env_file),
+    }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    // tokio::spawn(|| { println!("duma") });
-    let mut blockchain = Blockchain {
-        chain: vec![Blockchain::create_genesis_block()],
-        difficulty: 15,
-        pending_transactions: vec![],
-        mining_reward: 50.0,
-        accounts: Account::new(),
-        wallet: Wallet::new(),
+// Chain rebuilt from the data directory; an empty store is seeded with the genesis block
+// so later appends line up with heights. With `use_snapshot` account state comes from the
+// newest valid snapshot, otherwise every stored block is replayed and validated
+fn load_chain(config: &NodeConfig, wallet: Wallet, use_snapshot: bool) -> anyhow::Result<(Blockchain, ChainStore)> {
+    let mut blockchain = Blockchain::from_genesis(config.genesis()?, wallet);
+    let store = ChainStore::open(&config.data_dir);
+    let blocks = store.load()?;
+    if blocks.is_empty() {
+        store.rewrite(&blockchain.chain)?;
+    }
+    let snapshot = match use_snapshot {
+        true => SnapshotStore::open(&config.data_dir).latest_for(&blockchain.genesis.chain_id, &blocks)?,
+        false => None,
     };
+    match snapshot {
+        Some(snapshot) => {
+            tracing::info!("Starting from snapshot at height {}", snapshot.height);
+            snapshot::restore(&mut blockchain, blocks, &snapshot)?;
+        }
+        None => storage::restore(&mut blockchain, blocks)?,
+    }
+    tracing::info!("Loaded chain at height {} from {}", blockchain.height(), config.data_dir.display());
+    Ok((blockchain, store))
+}
 
-    // pub fn initialize_blockchain() {
-    let mut blockchain_test = GLOBAL_BLOCKCHAIN.lock().await;
-    // Perform initialization of the blockchain here
-    // e.g., add transactions, configure settings, etc.
-    // }
-
-    initialize(&mut blockchain);
-    // let b = initialize_blockchain();
+// Chain owned by the node wallet; rewards go to it unless another address is configured
+fn load_mining_chain(config: &NodeConfig) -> anyhow::Result<(Blockchain, ChainStore)> {
+    let wallet = config.wallet()?;
+    tracing::info!("Node wallet {}", wallet.address());
+    let (mut blockchain, store) = load_chain(config, wallet, true)?;
+    let miner_address = config.miner_address.unwrap_or_else(|| blockchain.wallet.address());
+    tracing::info!("Mining rewards go to {}", miner_address);
+    blockchain.miner_address = Some(miner_address);
+    blockchain.mempool_capacity = config.mempool_capacity;
+    Ok((blockchain, store))
+}
 
-    // start_mining_process(b).await;
+async fn run(config: NodeConfig) -> anyhow::Result<()> {
+    let (blockchain, store) = load_mining_chain(&config)?;
+    let chain_id = blockchain.genesis.chain_id.clone();
 
-    tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).init();
-    let domain = dotenvy::var("DOMAIN").expect("HSM Domain not found");
-    let port = dotenvy::var("PORT").expect("HSM Port not found");
+    let port = config.http_listen.port();
     let cors = CorsLayer::new()
-        .allow_origin(format!("{}:{}", domain, port).parse::<HeaderValue>().unwrap())
+        .allow_origin(format!("{}:{}", config.domain, port).parse::<HeaderValue>()?)
         .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
+        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, auth::API_KEY_HEADER]);
 
     let app_state = Arc::new(Mutex::new(blockchain));
+    task::spawn(storage::persist(app_state.clone(), store));
+    task::spawn(miner::run(app_state.clone()));
+    if config.snapshot_interval > 0 {
+        task::spawn(
+            snapshot::run(
+                app_state.clone(),
+                SnapshotStore::open(&config.data_dir),
+                config.snapshot_interval,
+                config.snapshot_keep
+            )
+        );
+    }
+
+    let node = p2p::P2p::new(app_state.clone(), chain_id, config.p2p_listen);
+    let status_state = route::StatusState {
+        blockchain: app_state.clone(),
+        p2p: node.clone(),
+        started: Instant::now(),
+    };
+    let seeds = config.peers.clone();
+    task::spawn(async move {
+        if let Err(e) = node.start(seeds).await {
+            tracing::error!("P2P networking stopped: {}", e);
+        }
+    });
+
+    // Only reachable when a faucet key is configured, which config limits to dev networks
+    let faucet_routes = match &config.faucet_secret_key {
+        Some(secret_key) => {
+            let faucet = faucet::Faucet::new(
+                SecretKey::from_str(secret_key.expose())?,
+                config.faucet_amount,
+                Duration::from_secs(config.faucet_cooldown_secs)
+            );
+            tracing::info!("Faucet enabled, paying {} from {}", config.faucet_amount, faucet.address());
+            route::faucet_routes(route::FaucetState {
+                blockchain: app_state.clone(),
+                faucet: Arc::new(Mutex::new(faucet)),
+            })
+        }
+        None => Router::new(),
+    };
 
+    let rate_limiter = Arc::new(
+        RateLimiter::new(config.rate_limit_per_minute, &config.route_rate_limits, &config.api_keys)
+    );
+    let auth = Arc::new(Auth::new(config.anonymous_role, &config.api_keys, rate_limiter.clone()));
     let app = Router::new()
         .merge(
             Router::new().route(
@@ -78,165 +159,202 @@ async fn main() -> anyhow::Result<()> {
         .merge(route::wallet_routes(app_state.clone()))
         .merge(route::transaction_routes(app_state.clone()))
         .merge(route::block_routes(app_state.clone()))
-        // .merge(route::wallet_routes(Arc::new(AppState { blockchain: blockchain.clone() })))
+        .merge(route::state_routes(app_state.clone()))
+        .merge(events::event_routes(app_state.clone()))
+        .merge(rpc::rpc_routes(app_state.clone()))
+        .merge(route::status_routes(status_state.clone()))
+        .merge(metrics::metrics_routes(status_state))
+        .merge(faucet_routes)
+        .merge(route::openapi_routes())
+        .fallback(route::not_found)
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
+        .layer(middleware::from_fn_with_state(auth, auth::authenticate))
+        .layer(middleware::from_fn(metrics::track_http))
+        .layer(middleware::from_fn(telemetry::trace_request))
         .layer(cors);
-    println!("🚀 Server started successfully, port {}", port);
-    // println!("🚀 HSM Server started successfully, port {}", hsm_port);
-    let addr = SocketAddr::from(([0, 0, 0, 0], port.parse().unwrap()));
+    tracing::info!("🚀 Server started successfully, port {}", port);
+    let addr = config.http_listen;
     let server1 = task::spawn(async move {
-        axum_server::bind(addr).serve(app.into_make_service()).await.unwrap();
+        axum_server
+            ::bind(addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>()).await
+            .unwrap();
     });
     server1.await.unwrap();
 
     Ok(())
 }
 
-pub type SharedBlockchain = Arc<Mutex<Blockchain>>;
-
-pub async fn start_mining_process(blockchain: SharedBlockchain) {
-    println!("Start mining process");
-    let mine_process = task::spawn({
-        // let b = blockchain.clone(); // Clone the shared blockchain
-        async move {
-            let mut blockchain = blockchain.lock().await;
-            let transactions = generate_transactions(10, &mut blockchain);
-            for (_index, transaction) in transactions.iter().enumerate() {
-                // println!("Transaction {}: {:?}", index + 1, transaction);
-                blockchain.add_new_tx(transaction.clone());
-            }
-            blockchain.mine_pending_transactions();
-            tracing_subscriber::fmt().with_max_level(tracing::Level::DEBUG).init();
-            let domain = dotenvy::var("DOMAIN").expect("HSM Domain not found");
-            let port = dotenvy::var("PORT").expect("HSM Port not found");
-            let cors = CorsLayer::new()
-                .allow_origin(format!("{}:{}", domain, port).parse::<HeaderValue>().unwrap())
-                .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
-                .allow_credentials(true)
-                .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE]);
-
-            let app_state = Arc::new(blockchain);
-
-            let app = Router::new()
-                .merge(
-                    Router::new().route(
-                        "/",
-                        get(|| async { "hello world" })
-                    )
-                )
-                // .merge(route::wallet_routes(app_state.clone()))
-                // .merge(route::transaction_routes(app_state.clone()))
-                // .merge(route::block_routes(app_state.clone()))
-                // .merge(route::wallet_routes(Arc::new(AppState { blockchain: blockchain.clone() })))
-                .layer(cors);
-            println!("🚀 Server started successfully, port {}", port);
-            // println!("🚀 HSM Server started successfully, port {}", hsm_port);
-            let addr = SocketAddr::from(([0, 0, 0, 0], port.parse().unwrap()));
-            let server1 = task::spawn(async move {
-                axum_server::bind(addr).serve(app.into_make_service()).await.unwrap();
-            });
-            server1.await.unwrap();
+fn mine(config: &NodeConfig, blocks: usize) -> anyhow::Result<()> {
+    let (mut blockchain, store) = load_mining_chain(config)?;
+    for _ in 0..blocks {
+        let block = blockchain.mine_block()?;
+        store.append(std::slice::from_ref(&block))?;
+        println!("Mined block {} at height {}", block.hash, blockchain.height());
+    }
+    Ok(())
+}
+
+fn verify(config: &NodeConfig) -> anyhow::Result<()> {
+    let (blockchain, _) = load_chain(config, Wallet::new(), false)?;
+    if !blockchain.is_chain_valid() {
+        return Err(anyhow::Error::msg("Chain failed validation"));
+    }
+    // Snapshots are trusted on startup, so check each against the state replayed up to it
+    let snapshots = SnapshotStore::open(&config.data_dir);
+    let mut replayed = Blockchain::from_genesis(blockchain.genesis.clone(), Wallet::new());
+    for path in snapshots.list()?.into_iter().rev() {
+        let snapshot = snapshots.load(&path)?;
+        snapshot
+            .verify(&blockchain.genesis.chain_id, &blockchain.chain)
+            .with_context(|| format!("Snapshot {} is invalid", path.display()))?;
+        for block in &blockchain.chain[replayed.chain.len()..=snapshot.height] {
+            replayed.add_block(block.clone())?;
+        }
+        if replayed.accounts.state_root() != snapshot.state_root {
+            return Err(anyhow::anyhow!("Snapshot {} does not match the replayed state", path.display()));
+        }
+        println!("Snapshot at height {} matches the chain", snapshot.height);
+    }
+    println!("Chain is valid");
+    println!("height:   {}", blockchain.height());
+    println!(
+        "tip:      {}",
+        blockchain
+            .get_latest_block()
+            .map(|block| block.hash.as_str())
+            .unwrap_or("")
+    );
+    println!("accounts: {}", blockchain.accounts.balances.len());
+    println!("supply:   {}", blockchain.total_supply());
+    println!("state:    {}", blockchain.accounts.state_root());
+    Ok(())
+}
+
+fn status(config: &NodeConfig, node: Option<String>, api_key: Option<&str>) -> anyhow::Result<()> {
+    let node = node.unwrap_or_else(|| {
+        let mut addr = config.http_listen;
+        if addr.ip().is_unspecified() {
+            addr.set_ip([127, 0, 0, 1].into());
         }
+        format!("http://{}", addr)
     });
-    mine_process.await.expect("Mining process failed");
-    // Wait for the mining process to complete
+    let url = format!("{}/status", node.trim_end_matches('/'));
+    let response: serde_json::Value = auth
+        ::with_api_key(ureq::get(&url), api_key)
+        .call()
+        .with_context(|| format!("Cannot reach node at {}", node))?
+        .into_json()?;
+    println!("{}", serde_json::to_string_pretty(&response["data"])?);
+    Ok(())
 }
 
-pub fn initialize_blockchain() -> SharedBlockchain {
-    // Create and return the shared blockchain wrapped in Arc<Mutex<>>
-    Arc::new(
-        Mutex::new(Blockchain {
-            chain: vec![Blockchain::create_genesis_block()],
-            difficulty: 5,
-            pending_transactions: vec![],
-            mining_reward: 50.0,
-            accounts: Account::new(),
-            wallet: Wallet::new(),
-        })
-    )
+// The key is only ever shown here; the config keeps its hash
+fn api_key(name: &str, role: Role) -> anyhow::Result<()> {
+    let (key, hash) = auth::generate_key()?;
+    println!("API key for {} (shown once): {}\n", name, key);
+    println!("Add to the node config:\n");
+    println!("[[api_keys]]\nname = {:?}\nrole = \"{}\"\nkey_hash = \"{}\"", name, role, hash);
+    Ok(())
 }
 
-fn initialize(blockchain: &mut Blockchain) {
-    let num_transactions = 1;
-    let transactions = generate_transactions(num_transactions, blockchain);
-    for (_index, transaction) in transactions.iter().enumerate() {
-        // println!("Transaction {}: {:?}", index + 1, transaction);
-        blockchain.add_transaction(transaction.clone());
+// A fresh faucet key per dev network, so no funded key ever has to be committed
+fn dev_setup(config: &NodeConfig, faucet_balance: f64, env_file: &Path) -> anyhow::Result<()> {
+    let mut genesis = GenesisSpec::load(&config.genesis_file)?;
+    if !genesis.dev_network {
+        return Err(anyhow::anyhow!("{} is not a dev_network genesis", config.genesis_file.display()));
+    }
+    // The allocation changes the genesis block, which an existing chain was built on
+    if !ChainStore::open(&config.data_dir).load()?.is_empty() {
+        return Err(anyhow::anyhow!("{} already holds a chain, use an empty data_dir", config.data_dir.display()));
     }
+    let faucet = Wallet::new();
+    genesis.allocations.insert(faucet.address(), faucet_balance);
+    genesis.validate()?;
+    genesis.save(&config.genesis_file)?;
 
-    // let (w_pk1, w_sk1) = Wallet::generate_wallet();
-    // let (w_pk2, _w_sk2) = Wallet::generate_wallet();
-    // let mut buf = [0u8; 32];
-    // getrandom::getrandom(&mut buf).unwrap();
-    // let latest_block = blockchain.get_latest_block().expect("no block available");
-    // let new_nonce = latest_block.nonce;
-    // // println!("ran block nonce: {:?}", buf);
-
-    // let message = digest(format!("{:?}{}{}", buf, w_pk1.to_string(), 10.0));
-    // let message_bytes = message[0..32].as_bytes();
-
-    // let mut msg = [0u8; 32];
-    // msg.copy_from_slice(&message_bytes);
-    // let encode_message = hex::encode(msg);
-
-    // blockchain.accounts.initialize(&w_pk1.to_string());
-    // blockchain.accounts.initialize(&w_pk2.to_string());
-    // let mut transfer_from_w1_to_w2 = Transaction {
-    //     from_address: w_pk1.to_string(),
-    //     to_address: w_pk2.to_string(),
-    //     pub_key: w_pk1,
-    //     msg: encode_message,
-    //     amount: 30.0,
-    //     signature: None,
-    //     status: transaction::TxStatus::PENDING,
-    //     nonce: new_nonce,
-    // };
-    // transfer_from_w1_to_w2.sign_transaction(&w_sk1);
-
-    // blockchain.add_transaction(transfer_from_w1_to_w2);
-
-    println!("🚀 Welcome to Duma Mining Simulator! 🚀");
-    // blockchain.mine_pending_transactions(private_key.public_key(&secp), private_key);
-
-    // Example of blockchain validation
-    println!("Is chain valid? {}", blockchain.is_chain_valid());
+    let line = format!("FAUCET_SECRET_KEY='{}'", faucet.secret_key().display_secret());
+    let existing = match fs::read_to_string(env_file) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Cannot read {}", env_file.display()));
+        }
+    };
+    let mut lines: Vec<String> = existing
+        .lines()
+        .filter(|existing| !existing.starts_with("FAUCET_SECRET_KEY="))
+        .map(str::to_string)
+        .collect();
+    lines.push(line);
+    fs::write(env_file, lines.join("\n") + "\n").with_context(|| format!("Cannot write {}", env_file.display()))?;
+
+    println!("Faucet {} funded with {} in {}", faucet.address(), faucet_balance, config.genesis_file.display());
+    println!("Its key is in {}; share both files only with nodes of this dev network", env_file.display());
+    Ok(())
 }
 
-fn generate_transactions(
-    num_transactions: usize,
-    blockchain: &mut Blockchain
-    // private_key: &SecretKey,
-    // secp: &Secp256k1<All>
-) -> Vec<Transaction> {
-    let mut transactions = Vec::new();
-    // let mut i = 0;
-    let (sender_pk, sender_sk) = Wallet::generate_wallet();
-    let (receiver_pk, _receiver_sk) = Wallet::generate_wallet();
-    for _ in 0..num_transactions {
-        let mut buf = [0u8; 32];
-        getrandom::getrandom(&mut buf).unwrap();
-        let message = digest(format!("{:?}{}{}", buf, sender_pk.to_string(), 10.0));
-        let message_bytes = message[0..32].as_bytes();
-
-        let mut msg = [0u8; 32];
-        msg.copy_from_slice(&message_bytes);
-        blockchain.accounts.initialize(&sender_pk.to_string());
-        blockchain.accounts.initialize(&receiver_pk.to_string());
-        let latest_block = blockchain.get_latest_block().expect("no block available");
-        // println!("block nonce: {}", latest_block.nonce);
-        let mut transaction = Transaction {
-            from_address: sender_pk.to_string(),
-            to_address: receiver_pk.to_string(),
-            pub_key: sender_pk,
-            msg: hex::encode(&msg),
-            amount: 10.0,
-            signature: None,
-            status: transaction::TxStatus::PENDING,
-            nonce: latest_block.nonce,
-        };
-
-        transaction.sign_transaction(&sender_sk);
-        transactions.push(transaction);
-    }
+fn take_snapshot(config: &NodeConfig) -> anyhow::Result<()> {
+    let (blockchain, _) = load_chain(config, Wallet::new(), true)?;
+    let snapshots = SnapshotStore::open(&config.data_dir);
+    let snapshot = Snapshot::capture(&blockchain)?;
+    let path = snapshots.save(&snapshot)?;
+    let pruned = snapshots.prune(config.snapshot_keep)?;
+    println!("Saved snapshot at height {} to {}, pruned {}", snapshot.height, path.display(), pruned);
+    Ok(())
+}
+
+fn export(config: &NodeConfig, out: &Path, from: usize, to: Option<usize>) -> anyhow::Result<()> {
+    let (blockchain, _) = load_chain(config, Wallet::new(), false)?;
+    let header = archive::export(&blockchain, from, to, out)?;
+    println!("Exported heights {}..={} to {}", header.from, header.to, out.display());
+    Ok(())
+}
+
+fn import(config: &NodeConfig, file: &Path) -> anyhow::Result<()> {
+    let (mut blockchain, store) = load_chain(config, Wallet::new(), false)?;
+    let summary = archive::import(&mut blockchain, file, |block| store.append(std::slice::from_ref(block)))?;
+    println!(
+        "Imported {} blocks, skipped {} already known, height is now {}",
+        summary.imported,
+        summary.skipped,
+        summary.height
+    );
+    Ok(())
+}
 
-    transactions
+// Keep the longest valid prefix of the store and drop the rest
+fn reindex(config: &NodeConfig) -> anyhow::Result<()> {
+    let store = ChainStore::open(&config.data_dir);
+    let (blocks, read_error) = store.load_readable()?;
+    let stored = blocks.len();
+    let mut blockchain = Blockchain::from_genesis(config.genesis()?, Wallet::new());
+    let mut blocks = blocks.into_iter();
+    if let Some(genesis) = blocks.next() {
+        if genesis.hash != blockchain.genesis_hash() {
+            return Err(anyhow::Error::msg("Stored chain was created from a different genesis spec"));
+        }
+    }
+    let mut stop_reason = read_error;
+    for block in blocks {
+        let height = blockchain.chain.len();
+        if let Err(e) = blockchain.add_block(block) {
+            stop_reason = Some(e.context(format!("Invalid stored block at height {}", height)));
+            break;
+        }
+    }
+    store.rewrite(&blockchain.chain)?;
+    match stop_reason {
+        Some(e) =>
+            println!(
+                "Kept {} of {} stored blocks, height is now {}: {:#}",
+                blockchain.chain.len(),
+                stored,
+                blockchain.height(),
+                e
+            ),
+        None => println!("Reindexed {} blocks, height is {}", blockchain.chain.len(), blockchain.height()),
+    }
+    Ok(())
 }
@@ -0,0 +1,106 @@
+use serde::{ Deserialize, Serialize };
+use sha256::digest;
+use utoipa::ToSchema;
+
+// Merkle root over a list of leaf hashes, duplicating the last node on odd levels
+pub fn merkle_root(leaves: &[String]) -> String {
+    if leaves.is_empty() {
+        return digest("");
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level.remove(0)
+}
+
+fn parent_level(level: &[String]) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            digest(format!("{}{}", pair[0], right))
+        })
+        .collect()
+}
+
+// One step of an inclusion proof: the sibling hash and which side it sits on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProofStep {
+    pub hash: String,
+    pub left: bool,
+}
+
+// Siblings from the leaf up to the root, built the same way merkle_root pairs nodes
+pub fn merkle_proof(leaves: &[String], index: usize) -> Option<Vec<ProofStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut proof = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut index = index;
+    while level.len() > 1 {
+        // A missing right sibling means the node was paired with itself
+        proof.push(ProofStep {
+            hash: level.get(index ^ 1).unwrap_or(&level[index]).clone(),
+            left: index & 1 == 1,
+        });
+        level = parent_level(&level);
+        index /= 2;
+    }
+    Some(proof)
+}
+
+// Recompute the root from a leaf and its proof
+pub fn verify_proof(leaf: &str, proof: &[ProofStep], root: &str) -> bool {
+    let computed = proof.iter().fold(leaf.to_string(), |hash, step| {
+        if step.left { digest(format!("{}{}", step.hash, hash)) } else { digest(format!("{}{}", hash, step.hash)) }
+    });
+    computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves() -> Vec<String> {
+        ["a", "b", "c"].into_iter().map(digest).collect()
+    }
+
+    #[test]
+    fn root_duplicates_the_last_node_on_odd_levels() {
+        assert_eq!(merkle_root(&[]), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(merkle_root(&leaves()[..1]), leaves()[0]);
+        assert_eq!(merkle_root(&leaves()), "0bdf27bf7ec894ca7cadfe491ec1a3ece840f117989e8c5e9bd7086467bf6c38");
+    }
+
+    #[test]
+    fn proofs_verify_against_the_root() {
+        let leaves = leaves();
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 2).unwrap();
+        assert_eq!(
+            proof,
+            vec![
+                ProofStep { hash: leaves[2].clone(), left: false },
+                ProofStep { hash: digest(format!("{}{}", leaves[0], leaves[1])), left: true }
+            ]
+        );
+        for (index, leaf) in leaves.iter().enumerate() {
+            assert!(verify_proof(leaf, &merkle_proof(&leaves, index).unwrap(), &root));
+        }
+        assert_eq!(merkle_proof(&leaves, 3), None);
+    }
+
+    #[test]
+    fn proofs_fail_for_other_leaves_or_roots() {
+        let leaves = leaves();
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 0).unwrap();
+        assert!(!verify_proof(&leaves[1], &proof, &root));
+        assert!(!verify_proof(&leaves[0], &proof, &leaves[0]));
+        let mut flipped = proof.clone();
+        flipped[0].left = !flipped[0].left;
+        assert!(!verify_proof(&leaves[0], &flipped, &root));
+    }
+}
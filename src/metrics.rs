@@ -0,0 +1,174 @@
+use crate::blockchain::{ Blockchain, HASHRATE_WINDOW };
+use crate::route::StatusState;
+use axum::{
+    extract::{ MatchedPath, Request, State },
+    http::{ header::CONTENT_TYPE, StatusCode },
+    middleware::Next,
+    response::{ IntoResponse, Response },
+    routing::get,
+    Router,
+};
+use prometheus::{
+    Encoder,
+    Gauge,
+    Histogram,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use std::{ future::Future, sync::LazyLock, time::Instant };
+use tokio::sync::{ Mutex, MutexGuard };
+use utoipa::OpenApi;
+
+// Every metric the node exports, registered once under the duma_ prefix
+pub struct Metrics {
+    registry: Registry,
+    pub blocks_mined: IntCounter,
+    pub mining_duration: Histogram,
+    // Hashes per second achieved while mining the last local block
+    pub hashrate: Gauge,
+    pub network_hashrate: Gauge,
+    pub chain_height: IntGauge,
+    pub mempool_transactions: IntGauge,
+    pub mempool_bytes: IntGauge,
+    pub transactions_accepted: IntCounter,
+    pub transactions_rejected: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub lock_wait: Histogram,
+    pub peers: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("duma".to_string()), None).expect("valid metrics prefix");
+        let metrics = Metrics {
+            blocks_mined: IntCounter::new("blocks_mined_total", "Blocks mined by this node").unwrap(),
+            mining_duration: Histogram::with_opts(
+                HistogramOpts::new("mining_duration_seconds", "Time spent on proof of work per block").buckets(
+                    vec![0.001, 0.01, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0]
+                )
+            ).unwrap(),
+            hashrate: Gauge::new("hashrate", "Local hashes per second while mining the last block").unwrap(),
+            network_hashrate: Gauge::new(
+                "network_hashrate",
+                "Estimated network hashes per second from recent block times"
+            ).unwrap(),
+            chain_height: IntGauge::new("chain_height", "Height of the chain tip").unwrap(),
+            mempool_transactions: IntGauge::new("mempool_transactions", "Transactions waiting in the mempool").unwrap(),
+            mempool_bytes: IntGauge::new("mempool_bytes", "Serialized size of the mempool").unwrap(),
+            transactions_accepted: IntCounter::new(
+                "transactions_accepted_total",
+                "Transactions accepted into the mempool, not counting ones requeued by a reorg"
+            ).unwrap(),
+            transactions_rejected: IntCounterVec::new(
+                Opts::new("transactions_rejected_total", "Transactions rejected, by reason"),
+                &["reason"]
+            ).unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+                &["method", "route", "status"]
+            ).unwrap(),
+            lock_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "blockchain_lock_wait_seconds",
+                    "Time spent waiting for the shared blockchain lock"
+                ).buckets(vec![0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0, 10.0])
+            ).unwrap(),
+            peers: IntGauge::new("peers", "Connected P2P peers").unwrap(),
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.blocks_mined.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.mining_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.hashrate.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.network_hashrate.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.chain_height.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.mempool_transactions.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.mempool_bytes.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.transactions_accepted.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.transactions_rejected.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.http_request_duration.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.lock_wait.clone())).unwrap();
+        metrics.registry.register(Box::new(metrics.peers.clone())).unwrap();
+        metrics
+    }
+
+    pub fn reject(&self, reason: &str) {
+        self.transactions_rejected.with_label_values(&[reason]).inc();
+    }
+
+    // Gauges read from the chain are refreshed when scraped rather than on every change
+    fn observe_chain(&self, blockchain: &Blockchain) {
+        self.chain_height.set(blockchain.height() as i64);
+        self.mempool_transactions.set(blockchain.pending_transactions.len() as i64);
+        let bytes: usize = blockchain.pending_transactions
+            .iter()
+            .filter_map(|tx| serde_json::to_vec(tx).ok())
+            .map(|bytes| bytes.len())
+            .sum();
+        self.mempool_bytes.set(bytes as i64);
+        self.network_hashrate.set(blockchain.network_hashrate(HASHRATE_WINDOW).unwrap_or(0.0));
+    }
+}
+
+// Lock the shared chain, recording how long the caller waited for it
+pub trait BlockchainLock {
+    fn lock_timed(&self) -> impl Future<Output = MutexGuard<'_, Blockchain>> + Send;
+}
+
+impl BlockchainLock for Mutex<Blockchain> {
+    async fn lock_timed(&self) -> MutexGuard<'_, Blockchain> {
+        let started = Instant::now();
+        let guard = self.lock().await;
+        METRICS.lock_wait.observe(started.elapsed().as_secs_f64());
+        guard
+    }
+}
+
+// Middleware recording latency per matched route, so path parameters do not explode labels
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    METRICS.http_request_duration
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+pub fn metrics_routes(app_state: StatusState) -> Router {
+    Router::new().route("/metrics", get(get_metrics)).with_state(app_state)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_metrics), tags((name = "node")))]
+pub struct MetricsApi;
+
+// Prometheus text exposition format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "node",
+    responses((status = 200, description = "Prometheus text format", content_type = "text/plain", body = String))
+)]
+async fn get_metrics(State(state): State<StatusState>) -> Response {
+    METRICS.peers.set(state.p2p.peer_count().await as i64);
+    METRICS.observe_chain(&*state.blockchain.lock_timed().await);
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    match encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        Ok(()) => ([(CONTENT_TYPE, encoder.format_type().to_string())], buffer).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,56 @@
+use crate::blockchain::{ Blockchain, ChainEvent };
+use crate::metrics::BlockchainLock;
+use std::sync::Arc;
+use tokio::{ sync::{ broadcast, Mutex }, task };
+use tracing::{ debug, error };
+
+// Mine the mempool into blocks for as long as it holds transactions, then wait until a
+// transaction is accepted or a reorg puts some back
+pub async fn run(blockchain: Arc<Mutex<Blockchain>>) {
+    let mut events = blockchain.lock_timed().await.subscribe();
+    loop {
+        match mine_next(&blockchain).await {
+            Ok(true) => {
+                continue;
+            }
+            Ok(false) => {}
+            Err(e) => error!("Failed to mine pending transactions: {:#}", e),
+        }
+        loop {
+            match events.recv().await {
+                Ok(ChainEvent::BlockConnected { .. }) => {}
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Mine one block from the mempool, false when there was nothing to mine. Proof of work runs
+// on a blocking thread without the chain lock, so requests and peers are served meanwhile
+async fn mine_next(blockchain: &Arc<Mutex<Blockchain>>) -> anyhow::Result<bool> {
+    let (mut block, accounts, mining_reward) = {
+        let blockchain = blockchain.lock_timed().await;
+        match blockchain.block_template()? {
+            Some((block, accounts)) => (block, accounts, blockchain.mining_reward),
+            None => {
+                return Ok(false);
+            }
+        }
+    };
+    let block = task::spawn_blocking(move || {
+        block.mine_block_with_capacity(&accounts, mining_reward);
+        block
+    }).await?;
+    let mut blockchain = blockchain.lock_timed().await;
+    if blockchain.get_latest_block().is_some_and(|tip| tip.hash != block.previous_hash) {
+        debug!(hash = %block.hash, "Tip moved while mining, discarding block");
+        return Ok(true);
+    }
+    blockchain.add_block(block)?;
+    Ok(true)
+}
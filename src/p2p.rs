@@ -0,0 +1,793 @@
+use crate::block::{ Block, BlockHeader };
+use crate::blockchain::{ Blockchain, ChainEvent };
+use crate::metrics::{ BlockchainLock, METRICS };
+use crate::gossip::{
+    misbehavior,
+    BanList,
+    Inventory,
+    Misbehavior,
+    RateLimiter,
+    SeenSet,
+    BAN_THRESHOLD,
+    MAX_INVENTORY_PER_MESSAGE,
+};
+use crate::sync::{ ChainSync, SyncProgress, SyncState, MAX_HEADERS_PER_MESSAGE, BODIES_PER_REQUEST };
+use rayon::prelude::*;
+use crate::transaction::Transaction;
+use futures_util::StreamExt;
+use serde::{ Deserialize, Serialize };
+use std::{ collections::HashMap, net::SocketAddr, sync::{ atomic::{ AtomicU64, Ordering }, Arc } };
+use tokio::{
+    io::AsyncWriteExt,
+    net::{ TcpListener, TcpStream },
+    sync::{ broadcast, mpsc, Mutex },
+    time::{ sleep, Duration },
+};
+use tokio_util::codec::{ FramedRead, LinesCodec, LinesCodecError };
+use tracing::{ debug, info, instrument, warn };
+
+pub const PROTOCOL_VERSION: u32 = 5;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Inventory remembered globally and per peer to stop relay loops
+const SEEN_CAPACITY: usize = 20_000;
+const PEER_KNOWN_CAPACITY: usize = 5_000;
+// Per-peer read budget: burst size and sustained messages per second; beyond it we simply
+// read more slowly, since honest peers send bursts while relaying or syncing
+const PEER_MESSAGE_BURST: u32 = 200;
+const PEER_MESSAGES_PER_SEC: u32 = 50;
+// Longest line accepted from a peer; a full batch of headers is well under this
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+// Handshake sent by both sides as soon as a connection opens
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Version {
+    pub protocol_version: u32,
+    pub chain_id: String,
+    pub genesis_hash: String,
+    pub best_height: usize,
+    pub listen_port: u16,
+}
+
+// Wire messages, sent as one JSON object per line
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data")]
+pub enum Message {
+    Version(Version),
+    VerAck,
+    Inventory(Vec<Inventory>),
+    GetData(Vec<Inventory>),
+    // Answers to GetData, one message per kind however many items were asked for
+    Blocks(Vec<Block>),
+    Transactions(Vec<Transaction>),
+    GetHeaders {
+        locator: Vec<String>,
+    },
+    Headers(Vec<BlockHeader>),
+    GetBlockBodies(Vec<String>),
+    BlockBodies(Vec<Block>),
+    GetMempool,
+}
+
+pub struct Peer {
+    pub connection_id: u64,
+    pub best_height: usize,
+    pub sender: mpsc::UnboundedSender<Message>,
+    // Inventory this peer announced or was sent, never announced back to it
+    pub known: SeenSet,
+}
+
+#[derive(Clone)]
+pub struct P2p {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    // Peers that completed the handshake, keyed by their advertised listen address
+    pub peers: Arc<Mutex<HashMap<SocketAddr, Peer>>>,
+    pub sync: Arc<Mutex<ChainSync>>,
+    pub seen: Arc<Mutex<SeenSet>>,
+    pub bans: Arc<Mutex<BanList>>,
+    pub chain_id: String,
+    pub listen_addr: SocketAddr,
+}
+
+impl P2p {
+    pub fn new(blockchain: Arc<Mutex<Blockchain>>, chain_id: String, listen_addr: SocketAddr) -> Self {
+        P2p {
+            blockchain,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            sync: Arc::new(Mutex::new(ChainSync::new())),
+            seen: Arc::new(Mutex::new(SeenSet::new(SEEN_CAPACITY))),
+            bans: Arc::new(Mutex::new(BanList::default())),
+            chain_id,
+            listen_addr,
+        }
+    }
+
+    // Accept inbound peers, keep dialing the seeds and relay local chain events
+    pub async fn start(self, seeds: Vec<SocketAddr>) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.listen_addr).await?;
+        info!("P2P listening on {}", self.listen_addr);
+
+        let events = self.blockchain.lock_timed().await.subscribe();
+        tokio::spawn(self.clone().relay_events(events));
+
+        tokio::spawn(self.clone().watch_sync());
+
+        for seed in seeds {
+            tokio::spawn(self.clone().maintain_seed(seed));
+        }
+
+        loop {
+            // Bans are checked at the handshake, once the peer's listen address is known
+            let (stream, addr) = listener.accept().await?;
+            debug!("Inbound peer connection from {}", addr);
+            tokio::spawn(self.clone().handle_connection(stream, addr));
+        }
+    }
+
+    pub async fn peer_count(&self) -> usize {
+        self.peers.lock().await.len()
+    }
+
+    pub async fn sync_progress(&self) -> SyncProgress {
+        self.sync.lock().await.progress()
+    }
+
+    // Announce items to every peer, each getting one message with the ones it does not know
+    pub async fn announce(&self, items: Vec<Inventory>) {
+        let mut seen = self.seen.lock().await;
+        for item in &items {
+            seen.insert(item.clone());
+        }
+        drop(seen);
+        let mut peers = self.peers.lock().await;
+        for peer in peers.values_mut() {
+            let unknown: Vec<Inventory> = items
+                .iter()
+                .filter(|item| peer.known.insert((*item).clone()))
+                .cloned()
+                .collect();
+            for chunk in unknown.chunks(MAX_INVENTORY_PER_MESSAGE) {
+                let _ = peer.sender.send(Message::Inventory(chunk.to_vec()));
+            }
+        }
+    }
+
+    // Announce new blocks and transactions, batching whatever queued up since the last round
+    async fn relay_events(self, mut events: broadcast::Receiver<ChainEvent>) {
+        loop {
+            let mut items = vec![];
+            let mut next = events.recv().await;
+            loop {
+                match next {
+                    Ok(ChainEvent::BlockConnected { block, .. }) => items.push(Inventory::Block(block.hash)),
+                    Ok(ChainEvent::Reorg { .. }) => {}
+                    Ok(ChainEvent::TransactionAccepted(tx)) => items.push(Inventory::Transaction(tx.id)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("P2P relay lagged behind by {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return;
+                    }
+                }
+                next = match events.try_recv() {
+                    Ok(event) => Ok(event),
+                    Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                        Err(broadcast::error::RecvError::Lagged(skipped))
+                    }
+                    Err(_) => {
+                        break;
+                    }
+                };
+            }
+            if !items.is_empty() {
+                self.announce(items).await;
+            }
+        }
+    }
+
+    async fn maintain_seed(self, seed: SocketAddr) {
+        loop {
+            let connected = self.peers.lock().await.contains_key(&seed);
+            if !connected && !self.bans.lock().await.is_banned(&seed) {
+                match TcpStream::connect(seed).await {
+                    Ok(stream) => {
+                        info!("Connected to seed peer {}", seed);
+                        self.clone().handle_connection(stream, seed).await;
+                    }
+                    Err(e) => debug!("Failed to connect to seed {}: {}", seed, e),
+                }
+            }
+            sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+
+    async fn local_version(&self) -> Version {
+        let blockchain = self.blockchain.lock_timed().await;
+        Version {
+            protocol_version: PROTOCOL_VERSION,
+            chain_id: self.chain_id.clone(),
+            genesis_hash: blockchain.genesis_hash(),
+            best_height: blockchain.height(),
+            listen_port: self.listen_addr.port(),
+        }
+    }
+
+    async fn handle_connection(self, stream: TcpStream, addr: SocketAddr) {
+        let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+        let (reader, mut writer) = stream.into_split();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let mut line = match serde_json::to_vec(&message) {
+                    Ok(line) => line,
+                    Err(_) => {
+                        continue;
+                    }
+                };
+                line.push(b'\n');
+                if writer.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let _ = sender.send(Message::Version(self.local_version().await));
+
+        let mut peer_addr: Option<SocketAddr> = None;
+        let mut score = 0;
+        let mut limiter = RateLimiter::new(PEER_MESSAGE_BURST, PEER_MESSAGES_PER_SEC);
+        let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_MESSAGE_BYTES));
+        while let Some(line) = lines.next().await {
+            let wait = limiter.acquire();
+            if !wait.is_zero() {
+                sleep(wait).await;
+            }
+            let result = match line {
+                Ok(line) =>
+                    match serde_json::from_str::<Message>(&line) {
+                        Ok(message) => self.dispatch(message, addr, &mut peer_addr, connection_id, &sender).await,
+                        Err(_) => Err(misbehavior(BAN_THRESHOLD, "Malformed message")),
+                    }
+                Err(LinesCodecError::MaxLineLengthExceeded) => Err(misbehavior(BAN_THRESHOLD, "Oversized message")),
+                Err(LinesCodecError::Io(_)) => {
+                    break;
+                }
+            };
+            let e = match result {
+                Ok(()) => {
+                    continue;
+                }
+                Err(e) => e,
+            };
+            match e.downcast_ref::<Misbehavior>() {
+                Some(reason) => {
+                    score += reason.score;
+                    debug!("Peer {} misbehaved: {}", addr, reason);
+                    if score >= BAN_THRESHOLD {
+                        let peer = peer_addr.unwrap_or(addr);
+                        warn!("Banning peer {}: {}", peer, reason);
+                        self.bans.lock().await.ban(peer);
+                        break;
+                    }
+                }
+                None => {
+                    warn!("Disconnecting peer {}: {}", addr, e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(listen_addr) = peer_addr {
+            let mut peers = self.peers.lock().await;
+            if peers.get(&listen_addr).map(|peer| peer.connection_id) == Some(connection_id) {
+                peers.remove(&listen_addr);
+                info!("Peer {} disconnected", listen_addr);
+            }
+        }
+    }
+
+    async fn dispatch(
+        &self,
+        message: Message,
+        addr: SocketAddr,
+        peer_addr: &mut Option<SocketAddr>,
+        connection_id: u64,
+        sender: &mpsc::UnboundedSender<Message>
+    ) -> anyhow::Result<()> {
+        match (message, *peer_addr) {
+                (Message::Version(version), None) => {
+                    let listen_addr = self.handle_version(version, addr, connection_id, sender).await?;
+                    *peer_addr = Some(listen_addr);
+                    Ok(())
+                }
+                (Message::Version(_), Some(_)) => Err(misbehavior(BAN_THRESHOLD, "Duplicate handshake")),
+                (_, None) => Err(anyhow::Error::msg("Message received before handshake")),
+                (message, Some(from)) => self.handle_message(message, from, sender).await,
+        }
+    }
+
+    async fn handle_version(
+        &self,
+        version: Version,
+        addr: SocketAddr,
+        connection_id: u64,
+        sender: &mpsc::UnboundedSender<Message>
+    ) -> anyhow::Result<SocketAddr> {
+        let local = self.local_version().await;
+        if version.protocol_version != local.protocol_version {
+            return Err(anyhow::Error::msg("Unsupported protocol version"));
+        }
+        if version.chain_id != local.chain_id {
+            return Err(anyhow::Error::msg("Peer is on a different chain id"));
+        }
+        if version.genesis_hash != local.genesis_hash {
+            return Err(anyhow::Error::msg("Peer has a different genesis block"));
+        }
+
+        let listen_addr = SocketAddr::new(addr.ip(), version.listen_port);
+        if self.bans.lock().await.is_banned(&listen_addr) {
+            return Err(anyhow::anyhow!("Peer {} is banned", listen_addr));
+        }
+        {
+            let mut peers = self.peers.lock().await;
+            if peers.contains_key(&listen_addr) {
+                return Err(anyhow::Error::msg("Already connected to this peer"));
+            }
+            peers.insert(listen_addr, Peer {
+                connection_id,
+                best_height: version.best_height,
+                sender: sender.clone(),
+                known: SeenSet::new(PEER_KNOWN_CAPACITY),
+            });
+        }
+        info!("Handshake complete with {} at height {}", listen_addr, version.best_height);
+
+        let _ = sender.send(Message::VerAck);
+        let _ = sender.send(Message::GetMempool);
+        self.maybe_start_sync().await;
+        Ok(listen_addr)
+    }
+
+    async fn handle_message(
+        &self,
+        message: Message,
+        from: SocketAddr,
+        sender: &mpsc::UnboundedSender<Message>
+    ) -> anyhow::Result<()> {
+        match message {
+            Message::Version(_) | Message::VerAck => {}
+            Message::Inventory(items) => {
+                self.handle_inventory(items, from, sender).await?;
+            }
+            Message::GetData(items) => {
+                let blockchain = self.blockchain.lock_timed().await;
+                let (mut blocks, mut transactions) = (vec![], vec![]);
+                for item in items.iter().take(MAX_INVENTORY_PER_MESSAGE) {
+                    match item {
+                        Inventory::Block(hash) => {
+                            if let Some(block) = blockchain.chain.iter().find(|block| &block.hash == hash) {
+                                blocks.push(block.clone());
+                            }
+                        }
+                        Inventory::Transaction(id) => {
+                            if let Some(tx) = blockchain.pending_transactions.iter().find(|tx| &tx.id == id) {
+                                transactions.push(tx.clone());
+                            }
+                        }
+                    }
+                }
+                if !blocks.is_empty() {
+                    let _ = sender.send(Message::Blocks(blocks));
+                }
+                if !transactions.is_empty() {
+                    let _ = sender.send(Message::Transactions(transactions));
+                }
+            }
+            Message::Blocks(blocks) => {
+                for block in blocks {
+                    self.mark_known(from, Inventory::Block(block.hash.clone())).await;
+                    self.handle_new_block(block, from).await?;
+                }
+            }
+            Message::Transactions(transactions) => {
+                for tx in transactions {
+                    self.mark_known(from, Inventory::Transaction(tx.id.clone())).await;
+                    self.handle_transaction(tx).await?;
+                }
+            }
+            Message::GetHeaders { locator } => {
+                let blockchain = self.blockchain.lock_timed().await;
+                let headers = blockchain.headers_after(&locator, MAX_HEADERS_PER_MESSAGE);
+                let _ = sender.send(Message::Headers(headers));
+            }
+            Message::Headers(headers) => {
+                self.handle_headers(headers, from, sender).await?;
+            }
+            Message::GetBlockBodies(hashes) => {
+                let blockchain = self.blockchain.lock_timed().await;
+                let bodies: Vec<Block> = hashes
+                    .iter()
+                    .take(BODIES_PER_REQUEST)
+                    .filter_map(|hash| blockchain.chain.iter().find(|block| &block.hash == hash))
+                    .cloned()
+                    .collect();
+                let _ = sender.send(Message::BlockBodies(bodies));
+            }
+            Message::BlockBodies(bodies) => {
+                self.handle_bodies(bodies, from).await?;
+            }
+            Message::GetMempool => {
+                let blockchain = self.blockchain.lock_timed().await;
+                let items: Vec<Inventory> = blockchain.pending_transactions
+                    .iter()
+                    .map(|tx| Inventory::Transaction(tx.id.clone()))
+                    .collect();
+                // Peers refuse larger announcements
+                for chunk in items.chunks(MAX_INVENTORY_PER_MESSAGE) {
+                    let _ = sender.send(Message::Inventory(chunk.to_vec()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn mark_known(&self, from: SocketAddr, item: Inventory) {
+        if let Some(peer) = self.peers.lock().await.get_mut(&from) {
+            peer.known.insert(item);
+        }
+    }
+
+    // Request announced items we have not seen from anyone yet
+    async fn handle_inventory(
+        &self,
+        items: Vec<Inventory>,
+        from: SocketAddr,
+        sender: &mpsc::UnboundedSender<Message>
+    ) -> anyhow::Result<()> {
+        if items.len() > MAX_INVENTORY_PER_MESSAGE {
+            return Err(misbehavior(20, "Oversized inventory announcement"));
+        }
+        let syncing = self.sync.lock().await.is_active();
+        for item in &items {
+            self.mark_known(from, item.clone()).await;
+        }
+        let mut seen = self.seen.lock().await;
+        let wanted: Vec<Inventory> = items
+            .into_iter()
+            .filter(|item| !(syncing && matches!(item, Inventory::Block(_))))
+            .filter(|item| seen.insert(item.clone()))
+            .collect();
+        if !wanted.is_empty() {
+            let _ = sender.send(Message::GetData(wanted));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "transaction", skip_all, fields(id = %tx.id))]
+    async fn handle_transaction(&self, tx: Transaction) -> anyhow::Result<()> {
+        self.seen.lock().await.insert(Inventory::Transaction(tx.id.clone()));
+        if !tx.verify_signature() {
+            METRICS.reject("invalid_signature");
+            return Err(misbehavior(BAN_THRESHOLD, "Transaction with an invalid signature"));
+        }
+        let mut blockchain = self.blockchain.lock_timed().await;
+        if tx.genesis_hash != blockchain.genesis_hash() {
+            METRICS.reject("wrong_chain");
+            return Err(misbehavior(BAN_THRESHOLD, "Transaction signed for another chain"));
+        }
+        let known =
+            blockchain.pending_transactions.iter().any(|pending| pending.id == tx.id) ||
+            blockchain.chain.iter().any(|block| block.find_transaction(&tx.id).is_some());
+        if known {
+            return Ok(());
+        }
+        // Balances and nonces may legitimately differ while blocks propagate, and a full
+        // mempool is not the peer's fault either, so a refused transaction is just dropped
+        if let Err(e) = blockchain.submit_transaction(tx) {
+            debug!("Dropping relayed transaction: {}", e);
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "block", skip_all, fields(hash = %block.hash, peer = %from))]
+    async fn handle_new_block(&self, block: Block, from: SocketAddr) -> anyhow::Result<()> {
+        if self.sync.lock().await.is_active() {
+            return Ok(());
+        }
+        self.seen.lock().await.insert(Inventory::Block(block.hash.clone()));
+        let height = {
+            let mut blockchain = self.blockchain.lock_timed().await;
+            if blockchain.chain.iter().any(|known| known.hash == block.hash) {
+                return Ok(());
+            }
+            if
+                block.difficulty != blockchain.difficulty ||
+                !block.has_valid_proof() ||
+                !block.transactions.iter().all(|txn| txn.verify_signature())
+            {
+                return Err(misbehavior(BAN_THRESHOLD, "Block with invalid proof of work or signatures"));
+            }
+            let tip_hash = blockchain.get_latest_block().map(|tip| tip.hash.clone());
+            if block.header().is_too_far_in_future() {
+                // Not necessarily the peer's fault; a later sync fetches it again once it is due
+                debug!("Dropping block too far in the future");
+                return Ok(());
+            }
+            if Some(&block.previous_hash) == tip_hash.as_ref() {
+                blockchain
+                    .add_block(block)
+                    .map_err(|e| misbehavior(BAN_THRESHOLD, &format!("Invalid block: {}", e)))?;
+                blockchain.height()
+            } else {
+                // We are behind or on a fork, the peer is at least one block ahead
+                blockchain.height() + 1
+            }
+        };
+        if let Some(peer) = self.peers.lock().await.get_mut(&from) {
+            peer.best_height = peer.best_height.max(height);
+        }
+        self.maybe_start_sync().await;
+        Ok(())
+    }
+
+    // Start a headers-first sync against the best peer if it is ahead of us
+    async fn maybe_start_sync(&self) {
+        let mut sync = self.sync.lock().await;
+        if sync.is_active() {
+            return;
+        }
+        let (height, locator) = {
+            let blockchain = self.blockchain.lock_timed().await;
+            (blockchain.height(), blockchain.locator())
+        };
+        let peers = self.peers.lock().await;
+        let best = peers.iter().max_by_key(|(_, peer)| peer.best_height);
+        if let Some((addr, peer)) = best {
+            if peer.best_height > height {
+                info!("Starting sync from {} (height {} -> {})", addr, height, peer.best_height);
+                sync.start(*addr, peer.best_height);
+                let _ = peer.sender.send(Message::GetHeaders { locator });
+            }
+        }
+    }
+
+    async fn handle_headers(
+        &self,
+        headers: Vec<BlockHeader>,
+        from: SocketAddr,
+        sender: &mpsc::UnboundedSender<Message>
+    ) -> anyhow::Result<()> {
+        let mut sync = self.sync.lock().await;
+        if sync.state != SyncState::Headers || sync.peer != Some(from) {
+            return Ok(());
+        }
+        let mut headers = headers;
+        let mut full_batch = headers.len() == MAX_HEADERS_PER_MESSAGE;
+        // Our clock may simply be behind the peer's; sync up to there and retry the rest later
+        if let Some(future) = headers.iter().position(BlockHeader::is_too_far_in_future) {
+            debug!("Dropping {} headers from {} that are too far in the future", headers.len() - future, from);
+            headers.truncate(future);
+            full_batch = false;
+        }
+        if !headers.is_empty() {
+            let blockchain = self.blockchain.lock_timed().await;
+            let first_parent = headers[0].previous_hash.clone();
+            let (ancestor, parent_hash) = if sync.rewind_to(&first_parent) {
+                (sync.ancestor, first_parent)
+            } else {
+                // First batch, or the peer reorged below every header it sent so far
+                sync.headers.clear();
+                let ancestor = match blockchain.find_ancestor(&headers[0]) {
+                    Some(ancestor) => ancestor,
+                    None => {
+                        sync.finish(SyncState::Idle);
+                        return Err(misbehavior(20, "Headers do not connect to our chain"));
+                    }
+                };
+                (ancestor, blockchain.chain[ancestor].hash.clone())
+            };
+            if let Err(e) = blockchain.validate_headers(&parent_hash, &headers) {
+                sync.finish(SyncState::Idle);
+                return Err(misbehavior(BAN_THRESHOLD, &format!("Invalid headers: {}", e)));
+            }
+            sync.add_headers(ancestor, headers);
+        }
+        info!(
+            "Sync progress: {} headers downloaded, target height {}",
+            sync.headers.len(),
+            sync.target_height
+        );
+
+        if full_batch {
+            let locator = sync.locator(self.blockchain.lock_timed().await.locator());
+            let _ = sender.send(Message::GetHeaders { locator });
+            return Ok(());
+        }
+
+        // The peer has no more headers, so this is its real height
+        let height = self.blockchain.lock_timed().await.height();
+        let peer_height = if sync.headers.is_empty() {
+            height
+        } else {
+            sync.ancestor + sync.headers.len()
+        };
+        if let Some(peer) = self.peers.lock().await.get_mut(&from) {
+            peer.best_height = peer_height;
+        }
+        if peer_height <= height {
+            // Nothing longer than what we already have
+            sync.finish(SyncState::Synced);
+            return Ok(());
+        }
+        sync.begin_bodies();
+        drop(sync);
+        self.request_bodies().await;
+        Ok(())
+    }
+
+    async fn request_bodies(&self) {
+        let mut sync = self.sync.lock().await;
+        if sync.state != SyncState::Bodies {
+            return;
+        }
+        let peers = self.peers.lock().await;
+        // The sync peer first, then any other peer that claims to be at least as far ahead
+        let mut candidates: Vec<SocketAddr> = sync.peer.into_iter().collect();
+        candidates.extend(
+            peers
+                .iter()
+                .filter(|(addr, peer)| Some(**addr) != sync.peer && peer.best_height >= sync.target_height)
+                .map(|(addr, _)| *addr)
+        );
+        for (addr, hashes) in sync.next_body_requests(&candidates) {
+            if let Some(peer) = peers.get(&addr) {
+                let _ = peer.sender.send(Message::GetBlockBodies(hashes));
+            }
+        }
+    }
+
+    async fn handle_bodies(&self, bodies: Vec<Block>, from: SocketAddr) -> anyhow::Result<()> {
+        // Check proof of work and signatures off the async runtime, in parallel
+        let bodies = tokio::task::spawn_blocking(move || {
+            let valid = bodies
+                .par_iter()
+                .all(|block| {
+                    block.has_valid_proof() &&
+                        block.transactions.iter().all(|txn| txn.verify_signature())
+                });
+            (valid, bodies)
+        }).await?;
+        let (valid, bodies) = bodies;
+        if !valid {
+            return Err(misbehavior(BAN_THRESHOLD, "Received an invalid block body"));
+        }
+
+        // The peer has these blocks, so they must not be announced back to it once connected
+        for block in &bodies {
+            self.mark_known(from, Inventory::Block(block.hash.clone())).await;
+        }
+        let mut sync = self.sync.lock().await;
+        if sync.state != SyncState::Bodies {
+            return Ok(());
+        }
+        let delivered = bodies.len();
+        let added = bodies.into_iter().try_for_each(|block| sync.add_body(block));
+        // Release the peer even for a bad delivery, so its bodies are requested elsewhere
+        sync.release_peer(from, delivered);
+        added.map_err(|e| misbehavior(10, &e.to_string()))?;
+
+        {
+            let mut blockchain = self.blockchain.lock_timed().await;
+            let next_parent = sync.next_parent_hash();
+            let fork_point = blockchain.chain.iter().position(|block| Some(&block.hash) == next_parent.as_ref());
+            let result = match fork_point {
+                // Bodies extend our tip, connect them as soon as they are contiguous
+                Some(index) if index == blockchain.height() => {
+                    sync.take_ready()
+                        .into_iter()
+                        .try_for_each(|block| blockchain.add_block(block))
+                        .map_err(|e| misbehavior(BAN_THRESHOLD, &format!("Invalid block: {}", e)))
+                }
+                // Bodies replace part of our chain, wait until the whole branch is here
+                Some(index) if sync.has_all_bodies() => {
+                    let mut candidate = blockchain.chain[..=index].to_vec();
+                    candidate.extend(sync.take_ready());
+                    blockchain
+                        .try_replace_chain(candidate)
+                        .map(|_| ())
+                        .map_err(|e| misbehavior(BAN_THRESHOLD, &format!("Invalid chain: {}", e)))
+                }
+                Some(_) => Ok(()),
+                None => Err(anyhow::Error::msg("Synced headers no longer connect to our chain")),
+            };
+            if let Err(e) = result {
+                sync.finish(SyncState::Idle);
+                return Err(e);
+            }
+            info!("Sync progress: block {}/{}", blockchain.height(), sync.target_height);
+            if sync.is_complete() {
+                info!("Sync complete at height {}", blockchain.height());
+                sync.finish(SyncState::Synced);
+                return Ok(());
+            }
+        }
+        drop(sync);
+        self.request_bodies().await;
+        Ok(())
+    }
+
+    // Periodically restart stalled syncs and catch up with peers that moved ahead
+    async fn watch_sync(self) {
+        loop {
+            sleep(SYNC_CHECK_INTERVAL).await;
+            let stalled = {
+                let mut sync = self.sync.lock().await;
+                if sync.is_stalled() {
+                    warn!("Sync with {:?} stalled, restarting", sync.peer);
+                    sync.finish(SyncState::Idle);
+                    true
+                } else {
+                    false
+                }
+            };
+            if stalled || !self.sync.lock().await.is_active() {
+                self.maybe_start_sync().await;
+            } else {
+                self.request_bodies().await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::GenesisSpec;
+    use crate::wallet::Wallet;
+    use std::collections::BTreeMap;
+
+    fn genesis() -> GenesisSpec {
+        GenesisSpec {
+            chain_id: "duma-test".to_string(),
+            timestamp: 1715558400,
+            initial_difficulty: 1,
+            block_capacity: 4,
+            mining_reward: 50.0,
+            dev_network: true,
+            allocations: BTreeMap::new(),
+        }
+    }
+
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    // More blocks than a peer's message burst, so the sync only completes if neither side
+    // floods the other into a ban
+    #[tokio::test]
+    async fn fresh_node_syncs_past_the_message_burst() {
+        let blocks = (PEER_MESSAGE_BURST as usize) + 100;
+        let mut chain = Blockchain::from_genesis(genesis(), Wallet::new());
+        for _ in 0..blocks {
+            chain.mine_block().unwrap();
+        }
+        let (a_addr, b_addr) = (free_addr(), free_addr());
+        let a = P2p::new(Arc::new(Mutex::new(chain)), "duma-test".to_string(), a_addr);
+        let b_chain = Arc::new(Mutex::new(Blockchain::from_genesis(genesis(), Wallet::new())));
+        let b = P2p::new(b_chain.clone(), "duma-test".to_string(), b_addr);
+        tokio::spawn(a.clone().start(vec![]));
+        tokio::spawn(b.clone().start(vec![a_addr]));
+
+        let synced = tokio::time::timeout(Duration::from_secs(60), async {
+            while b_chain.lock().await.height() < blocks {
+                sleep(Duration::from_millis(100)).await;
+            }
+        }).await;
+        assert!(synced.is_ok(), "B stopped at height {}", b_chain.lock().await.height());
+        assert!(!a.bans.lock().await.is_banned(&b_addr));
+        assert!(!b.bans.lock().await.is_banned(&a_addr));
+    }
+}
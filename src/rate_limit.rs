@@ -0,0 +1,151 @@
+use crate::api::ApiError;
+use crate::auth::{ ApiClient, ApiKeyConfig };
+use axum::{
+    extract::{ ConnectInfo, MatchedPath, Request, State },
+    http::{ header::RETRY_AFTER, HeaderValue, StatusCode },
+    middleware::Next,
+    response::{ IntoResponse, Response },
+};
+use std::{
+    collections::{ BTreeMap, HashMap },
+    net::{ IpAddr, Ipv4Addr, SocketAddr },
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant },
+};
+
+// Buckets untouched for this long are full again, so dropping them changes nothing
+const IDLE_BUCKET: Duration = Duration::from_secs(60);
+// Number of buckets above which idle ones are swept
+const SWEEP_THRESHOLD: usize = 10_000;
+
+// Who a quota is counted against: the API key when one was presented, otherwise the IP
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Key(String),
+    Ip(IpAddr),
+}
+
+// Token bucket holding up to a minute's worth of requests, refilled continuously
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Per-client quotas in requests per minute. A route listed in `routes` gets its own bucket,
+// every other route shares the client's default bucket; 0 means unlimited
+pub struct RateLimiter {
+    default_per_minute: u32,
+    routes: BTreeMap<String, u32>,
+    // Default quota of individual API keys, by key name
+    keys: HashMap<String, u32>,
+    buckets: Mutex<HashMap<(Client, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(default_per_minute: u32, routes: &BTreeMap<String, u32>, api_keys: &[ApiKeyConfig]) -> Self {
+        RateLimiter {
+            default_per_minute,
+            routes: routes.clone(),
+            keys: api_keys
+                .iter()
+                .filter_map(|key| key.rate_limit_per_minute.map(|quota| (key.name.clone(), quota)))
+                .collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take one request from the client's bucket for the route, or return the seconds until
+    // the next one would be allowed
+    fn check(&self, client: Client, route: &str) -> Result<(), u64> {
+        let (bucket_route, per_minute) = match self.routes.get(route) {
+            Some(quota) => (route, *quota),
+            None => {
+                let quota = match &client {
+                    Client::Key(name) => self.keys.get(name).copied().unwrap_or(self.default_per_minute),
+                    Client::Ip(_) => self.default_per_minute,
+                };
+                ("*", quota)
+            }
+        };
+        if per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > SWEEP_THRESHOLD {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < IDLE_BUCKET);
+        }
+        let bucket = buckets
+            .entry((client, bucket_route.to_string()))
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        }
+    }
+
+    // Count a request refused before it reaches `limit`, such as one with an unknown API key,
+    // against its IP so guessing keys is throttled like any other anonymous traffic. Returns the
+    // 429 to send when the IP is over its quota
+    pub fn charge_ip(&self, request: &Request) -> Option<Response> {
+        self.check(Client::Ip(client_ip(request)), "*").err().map(too_many_requests)
+    }
+}
+
+fn client_ip(request: &Request) -> IpAddr {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip())
+}
+
+fn too_many_requests(retry_after: u64) -> Response {
+    let error = ApiError {
+        retry_after_secs: Some(retry_after),
+        ..ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded")
+    };
+    let mut response = error.into_response();
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+// The caller's quotas, left in the request extensions for handlers that carry out several
+// requests at once, such as JSON-RPC batches
+#[derive(Clone)]
+pub struct ClientQuota {
+    limiter: Arc<RateLimiter>,
+    client: Client,
+}
+
+impl ClientQuota {
+    // Count one request against the quota of `route`, as if the client had called it directly
+    pub fn charge(&self, route: &str) -> Result<(), u64> {
+        self.limiter.check(self.client.clone(), route)
+    }
+}
+
+// Runs inside auth, so requests with an API key are counted against the key
+pub async fn limit(State(limiter): State<Arc<RateLimiter>>, mut request: Request, next: Next) -> Response {
+    let client = match request.extensions().get::<ApiClient>() {
+        Some(ApiClient(name)) => Client::Key(name.clone()),
+        None => Client::Ip(client_ip(&request)),
+    };
+    // Unmatched paths are cheap 404s and all count against the default bucket
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("*", |path| path.as_str())
+        .to_string();
+    if let Err(retry_after) = limiter.check(client.clone(), &route) {
+        return too_many_requests(retry_after);
+    }
+    request.extensions_mut().insert(ClientQuota { limiter, client });
+    next.run(request).await
+}
@@ -1,283 +1,783 @@
-use axum::{ routing::{ get, post }, Router, extract::Path };
+use axum::{ routing::{ get, post }, Router, extract::ConnectInfo };
 use secp256k1::{ PublicKey, Secp256k1, SecretKey };
 use serde::{ Deserialize, Serialize };
-use sha256::digest;
-use crate::{ blockchain::Blockchain, transaction::{ self, Transaction }, wallet::Wallet };
-use axum::{ extract::State, http::StatusCode, response::IntoResponse, Json };
-use std::{ str::FromStr, sync::Arc };
-use tokio::sync::Mutex;
+use crate::{
+    address::Address,
+    api::{
+        ApiError,
+        ApiResponse,
+        ApiResult,
+        Balance,
+        ChainTransaction,
+        ChainValidity,
+        HdAddress,
+        Headers,
+        NewHdWallet,
+        NewWallet,
+        NodeStatus,
+        RestoredHdWallet,
+        StateProof,
+        TransactionPage,
+        TransactionProof,
+        TransactionReceipt,
+        WalletSummary,
+        WalletTransactions,
+    },
+    auth,
+    block::{ Block, BlockHeader },
+    blockchain::{ Blockchain, SortOrder, TxFilter, TxPosition, HASHRATE_WINDOW },
+    events,
+    extract::{ Json, Path, Query },
+    faucet::{ Faucet, FaucetError },
+    hd_wallet::{ DerivationPath, HdWallet, DEFAULT_DERIVATION_PATH, DEFAULT_GAP_LIMIT, MAX_GAP_LIMIT },
+    metrics::{ self, BlockchainLock },
+    p2p::P2p,
+    rpc,
+    telemetry::Secret,
+    transaction::{ Transaction, TxStatus },
+    wallet::Wallet,
+};
+use axum::{ extract::State, http::StatusCode, Extension };
+use std::{ net::SocketAddr, str::FromStr, sync::Arc, time::Instant };
+use tokio::{ sync::Mutex, task };
+use utoipa::{
+    openapi::security::{ ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme },
+    IntoParams,
+    Modify,
+    OpenApi,
+    ToSchema,
+};
 
 use tracing::debug;
 
 pub fn wallet_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
     Router::new()
+        .route("/wallet", get(get_node_wallet))
+        .route("/wallet/transfer", post(transfer_from_node_wallet))
         .route("/wallet/new", post(create_wallet))
+        .route("/wallet/hd/new", post(create_hd_wallet))
+        .route("/wallet/hd/restore", post(restore_hd_wallet))
         .route("/wallet/:public_key", get(get_wallet_details))
-        .route("/wallet/:public_key/balance", get(get_wallet_balance))
+        .route("/wallet/:address/balance", get(get_wallet_balance))
+        .route("/wallet/:address/transactions", get(get_wallet_transactions))
         .with_state(app_state)
 }
 
 pub fn transaction_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
     Router::new()
         .route("/transaction/create", post(add_transaction))
+        .route("/transaction/submit", post(submit_transaction))
         .route("/transactions", get(get_all_txs))
         .route("/transaction/:hash", get(get_tx_by_hash))
+        .route("/transaction/:hash/proof", get(get_tx_proof))
         .with_state(app_state)
 }
 
+#[derive(Clone)]
+pub struct FaucetState {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub faucet: Arc<Mutex<Faucet>>,
+}
+
+pub fn faucet_routes(app_state: FaucetState) -> Router {
+    Router::new().route("/faucet", post(request_faucet)).with_state(app_state)
+}
+
+#[derive(Clone)]
+pub struct StatusState {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub p2p: P2p,
+    pub started: Instant,
+}
+
+pub fn status_routes(app_state: StatusState) -> Router {
+    Router::new().route("/status", get(get_status)).with_state(app_state)
+}
+
+pub fn state_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
+    Router::new().route("/state/proof/:address", get(get_state_proof)).with_state(app_state)
+}
+
 pub fn block_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
     Router::new()
         .route("/blocks", get(get_all_blocks))
         .route("/blocks/validate", get(validate_chain))
+        .route("/headers", get(get_headers))
         .with_state(app_state)
 }
 
-async fn validate_chain(State(data): State<Arc<Mutex<Blockchain>>>) -> Result<
-    impl IntoResponse,
-    (StatusCode, Json<serde_json::Value>)
-> {
-    let blockchain = data.lock().await;
-    let valid = blockchain.is_chain_valid();
-    let json_response =
-        serde_json::json!({
-            "status": "success",
-            "data": {
-                "is_valid": valid,
-                }
-        });
+pub fn openapi_routes() -> Router {
+    Router::new().route("/openapi.json", get(get_openapi))
+}
 
-    Ok((StatusCode::OK, Json(json_response)))
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Duma node API", description = "HTTP API of a Duma proof-of-work node"),
+    paths(
+        validate_chain,
+        add_transaction,
+        submit_transaction,
+        get_all_txs,
+        get_tx_by_hash,
+        get_tx_proof,
+        create_wallet,
+        create_hd_wallet,
+        restore_hd_wallet,
+        get_node_wallet,
+        get_wallet_details,
+        transfer_from_node_wallet,
+        get_wallet_balance,
+        get_wallet_transactions,
+        get_all_blocks,
+        get_headers,
+        get_state_proof,
+        get_status,
+        request_faucet
+    ),
+    modifiers(&ApiKeySecurity),
+    security((), ("bearer" = []), ("api_key" = [])),
+    tags(
+        (name = "blocks"),
+        (name = "transactions"),
+        (name = "wallets"),
+        (name = "state"),
+        (name = "node"),
+        (name = "faucet", description = "Only served on dev networks with a faucet key configured")
+    )
+)]
+pub struct ApiDoc;
+
+// Either header carries the same key; the role each route needs is in auth::required_role
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build())
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(auth::API_KEY_HEADER.as_str())))
+        );
+    }
+}
+
+// The whole HTTP API, including the endpoints documented next to their own modules
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut doc = ApiDoc::openapi();
+    doc.merge(rpc::RpcApi::openapi());
+    doc.merge(events::EventsApi::openapi());
+    doc.merge(metrics::MetricsApi::openapi());
+    doc
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+async fn get_openapi() -> axum::Json<utoipa::openapi::OpenApi> {
+    axum::Json(openapi())
+}
+
+// Unknown paths get the same error envelope as everything else
+pub async fn not_found() -> ApiError {
+    ApiError::not_found("No such route")
+}
+
+#[utoipa::path(
+    get,
+    path = "/blocks/validate",
+    tag = "blocks",
+    responses((status = 200, body = ApiResponse<ChainValidity>))
+)]
+async fn validate_chain(State(data): State<Arc<Mutex<Blockchain>>>) -> ApiResult<ChainValidity> {
+    let blockchain = data.lock_timed().await;
+    Ok(ApiResponse::success(ChainValidity { is_valid: blockchain.is_chain_valid() }))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 struct AddTransaction {
     to_address: String,
-    secret_key: String,
+    #[schema(value_type = String)]
+    secret_key: Secret<String>,
     amount: f64,
 }
 
+// Sign with a key sent by the client and queue it for mining; /transaction/submit keeps the key local
+#[utoipa::path(
+    post,
+    path = "/transaction/create",
+    tag = "transactions",
+    request_body = AddTransaction,
+    responses(
+        (status = 200, body = ApiResponse<TransactionReceipt>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Rate limited, retry_after_secs is set")
+    )
+)]
 async fn add_transaction(
-    State(mut data): State<Arc<Mutex<Blockchain>>>,
+    State(data): State<Arc<Mutex<Blockchain>>>,
     Json(payload): Json<AddTransaction>
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    // let b = Arc::make_mut(&mut data);
-    // let blockchain: &mut Arc<tokio::sync::Mutex<crate::blockchain::Blockchain>> = &mut b.blockchain;
+) -> ApiResult<TransactionReceipt> {
     let secp = Secp256k1::new();
-    // let blockchain = data.blockchain.clone();
-    let mut blockchain = data.lock().await;
-    // let sk = SecretKey::from_str(&payload.secret_key);
-
-    let sk = match SecretKey::from_str(&payload.secret_key) {
-        Ok(key) => key,
-        Err(_) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": "Invalid secret key format"})),
-            ));
-        }
-    };
+    let mut blockchain = data.lock_timed().await;
+    let sk = SecretKey::from_str(payload.secret_key.expose()).map_err(|_| {
+        ApiError::bad_request("Invalid secret key format")
+    })?;
 
     let public_key = PublicKey::from_secret_key(&secp, &sk);
-    if !blockchain.accounts.is_valid_address(&payload.to_address) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Invalid Address"})),
-        ));
-    }
-    if !blockchain.accounts.is_valid_address(&public_key.to_string()) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Invalid Address"})),
-        ));
-    }
+    let to_address = parse_address(&payload.to_address)?;
+    let from_address = Address::from_public_key(&public_key);
 
-    if &payload.amount > blockchain.get_balance(&public_key.to_string()) {
-        println!(
-            "Balance insufficient: {} | {}",
-            &payload.amount,
-            blockchain.get_balance(&public_key.to_string())
+    if payload.amount > blockchain.get_balance(&from_address) {
+        debug!(
+            amount = payload.amount,
+            balance = blockchain.get_balance(&from_address),
+            "Insufficient balance for transfer"
         );
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Insufficient funds"})),
-        ));
+        return Err(ApiError::bad_request("Insufficient funds"));
     }
-    debug!("Sender pk: {}", public_key);
-    debug!("Sender payload address: {:?}", payload);
-    let mut buf = [0u8; 32];
-    getrandom::getrandom(&mut buf).unwrap();
-    let message = digest(format!("{:?}{}{}", buf, public_key.to_string(), payload.amount));
-    let message_bytes = message[0..32].as_bytes();
-
-    let mut msg = [0u8; 32];
-    msg.copy_from_slice(&message_bytes);
-
-    blockchain.accounts.initialize(&public_key.to_string());
-    blockchain.accounts.initialize(&payload.to_address);
-    let latest_block = blockchain.get_latest_block().expect("No block available");
-    let encode_message = hex::encode(msg);
-    let mut transaction = Transaction {
-        from_address: public_key.to_string(),
-        to_address: payload.to_address,
-        pub_key: public_key,
-        msg: encode_message,
-        amount: payload.amount,
-        signature: None,
-        status: transaction::TxStatus::PENDING,
-        nonce: latest_block.nonce,
-    };
+    debug!(from = %from_address, payload = ?payload, "Signing transfer");
+    let nonce = blockchain.next_nonce(&from_address);
+    let transaction = Transaction::new_signed(&sk, &blockchain.genesis_hash(), to_address, payload.amount, nonce);
+    let tx = blockchain.submit_transaction(transaction).map_err(ApiError::bad_request)?;
+    Ok(ApiResponse::success(TransactionReceipt { tx }))
+}
 
-    transaction.sign_transaction(&sk);
-    match blockchain.add_transaction(transaction.clone()) {
-        Ok(tx) => {
-            let json_response =
-                serde_json::json!({
-            "status": "success",
-            "data": {
-                "tx": tx,
-            }
-        });
+// Transactions signed by the client, so secret keys never reach the node
+#[utoipa::path(
+    post,
+    path = "/transaction/submit",
+    tag = "transactions",
+    request_body = Transaction,
+    responses(
+        (status = 200, body = ApiResponse<TransactionReceipt>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Rate limited, retry_after_secs is set")
+    )
+)]
+async fn submit_transaction(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Json(transaction): Json<Transaction>
+) -> ApiResult<TransactionReceipt> {
+    let mut blockchain = data.lock_timed().await;
+    let tx = blockchain.submit_transaction(transaction).map_err(ApiError::bad_request)?;
+    Ok(ApiResponse::success(TransactionReceipt { tx }))
+}
 
-            Ok((StatusCode::OK, Json(json_response)))
+pub const DEFAULT_TRANSACTIONS_PER_PAGE: usize = 100;
+pub const MAX_TRANSACTIONS_PER_PAGE: usize = 1000;
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TransactionsQuery {
+    /// next_cursor of the previous page
+    cursor: Option<String>,
+    /// Page size, 100 by default and at most 1000
+    limit: Option<usize>,
+    /// Chain order, oldest first by default
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    /// failed or success; pending transactions are in the mempool, not the chain
+    status: Option<String>,
+    /// Sender or recipient
+    address: Option<String>,
+    min_amount: Option<f64>,
+    max_amount: Option<f64>,
+    from_height: Option<usize>,
+    to_height: Option<usize>,
+}
+
+impl TransactionsQuery {
+    fn filter(&self) -> anyhow::Result<TxFilter> {
+        if let (Some(min), Some(max)) = (self.min_amount, self.max_amount) {
+            if min > max {
+                return Err(anyhow::Error::msg("min_amount is above max_amount"));
+            }
         }
-        Err(e) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": format!("{}", e)})),
-            ));
+        if let (Some(from), Some(to)) = (self.from_height, self.to_height) {
+            if from > to {
+                return Err(anyhow::Error::msg("from_height is above to_height"));
+            }
+        }
+        let status = self.status.as_deref().map(str::parse).transpose()?;
+        if status == Some(TxStatus::PENDING) {
+            return Err(anyhow::Error::msg("status=pending never matches, chain transactions are failed or success"));
         }
+        Ok(TxFilter {
+            status,
+            address: self.address.as_deref().map(str::parse).transpose()?,
+            min_amount: self.min_amount,
+            max_amount: self.max_amount,
+            from_height: self.from_height,
+            to_height: self.to_height,
+        })
     }
 }
 
-async fn get_all_txs(State(data): State<Arc<Mutex<Blockchain>>>) -> Result<
-    impl IntoResponse,
-    (StatusCode, Json<serde_json::Value>)
-> {
-    let blockchain = data.lock().await;
-    let txs = blockchain.get_all_tx();
-    let json_response =
-        serde_json::json!({
-        "status": "success",
-        "data": {
-            "transactions": txs,
-            "total transactions": txs.len()
-        }
-    });
-
-    Ok((StatusCode::OK, Json(json_response)))
+// Transactions in the chain a page at a time, e.g.
+// GET /transactions?address=duma1...&status=success&order=desc&limit=50&cursor=120-3
+#[utoipa::path(
+    get,
+    path = "/transactions",
+    tag = "transactions",
+    params(TransactionsQuery),
+    responses((status = 200, body = ApiResponse<TransactionPage>), (status = 400, body = ApiError))
+)]
+async fn get_all_txs(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Query(query): Query<TransactionsQuery>
+) -> ApiResult<TransactionPage> {
+    let filter = query.filter().map_err(ApiError::bad_request)?;
+    let cursor: Option<TxPosition> = query.cursor.as_deref().map(str::parse).transpose().map_err(ApiError::bad_request)?;
+    let limit = query.limit.unwrap_or(DEFAULT_TRANSACTIONS_PER_PAGE).clamp(1, MAX_TRANSACTIONS_PER_PAGE);
+    let blockchain = data.lock_timed().await;
+    let page = blockchain.list_transactions(&filter, query.order, cursor, limit);
+    Ok(
+        ApiResponse::success(TransactionPage {
+            transactions: page.transactions
+                .into_iter()
+                .map(|(position, tx)| ChainTransaction::new(position, tx))
+                .collect(),
+            next_cursor: page.next_cursor.map(|cursor| cursor.to_string()),
+        })
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/transaction/{hash}",
+    tag = "transactions",
+    params(("hash" = String, Path, description = "Transaction id")),
+    responses((status = 200, body = ApiResponse<ChainTransaction>), (status = 404, body = ApiError))
+)]
 async fn get_tx_by_hash(
     State(data): State<Arc<Mutex<Blockchain>>>,
     Path(hash): Path<String>
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    let blockchain = data.lock().await;
-    let tx = blockchain.chain.iter().find_map(|block| block.find_transaction_by_signature(&hash));
-    match tx {
-        Some(tx) => {
-            let json_response =
-                serde_json::json!({
-                    "status": "success",
-                    "data": {
-                        "tx": tx,
-                    }
-                });
-            Ok(Json(json_response))
-        }
-        None => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({"error": format!("No tx found")})),
-            ));
+) -> ApiResult<ChainTransaction> {
+    let blockchain = data.lock_timed().await;
+    let (position, tx) = blockchain.find_transaction(&hash).ok_or_else(|| ApiError::not_found("No tx found"))?;
+    Ok(ApiResponse::success(ChainTransaction::new(position, tx.clone())))
+}
+
+// Merkle proof that a mined transaction is included in its block, for light clients
+#[utoipa::path(
+    get,
+    path = "/transaction/{hash}/proof",
+    tag = "transactions",
+    params(("hash" = String, Path, description = "Transaction id")),
+    responses((status = 200, body = ApiResponse<TransactionProof>), (status = 404, body = ApiError))
+)]
+async fn get_tx_proof(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Path(hash): Path<String>
+) -> ApiResult<TransactionProof> {
+    let blockchain = data.lock_timed().await;
+    let (height, block, tx, proof) = blockchain.chain
+        .iter()
+        .enumerate()
+        .find_map(|(height, block)| block.transaction_proof(&hash).map(|(tx, proof)| (height, block, tx, proof)))
+        .ok_or_else(|| ApiError::not_found("No tx found"))?;
+    Ok(
+        ApiResponse::success(TransactionProof {
+            tx: tx.clone(),
+            height,
+            block_hash: block.hash.clone(),
+            merkle_root: block.merkle_root(),
+            proof,
+        })
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+struct CreateWallet {
+    // When given, the key is only returned inside an encrypted keystore
+    #[schema(value_type = Option<String>)]
+    password: Option<Secret<String>>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/new",
+    tag = "wallets",
+    request_body(content = Option<CreateWallet>),
+    responses(
+        (status = 200, body = ApiResponse<NewWallet>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Rate limited, retry_after_secs is set")
+    )
+)]
+async fn create_wallet(payload: Option<Json<CreateWallet>>) -> ApiResult<NewWallet> {
+    let Json(payload) = payload.unwrap_or_default();
+    let wallet = Wallet::new();
+    let wallet_address = wallet.address();
+    let new_wallet = match payload.password {
+        Some(password) => {
+            // scrypt is deliberately slow, keep it off the async runtime
+            let keystore = task
+                ::spawn_blocking(move || wallet.export_keystore(password.expose()))
+                .await
+                .map_err(ApiError::internal)?
+                .map_err(ApiError::bad_request)?;
+            NewWallet {
+                address: wallet_address,
+                public_key: keystore.public_key.clone(),
+                secret_key: None,
+                keystore: Some(keystore),
+            }
         }
+        None =>
+            NewWallet {
+                address: wallet_address,
+                public_key: wallet.public_key().to_string(),
+                secret_key: Some(format!("{}", wallet.secret_key().display_secret())),
+                keystore: None,
+            },
+    };
+    Ok(ApiResponse::success(new_wallet))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+struct CreateHdWallet {
+    word_count: Option<usize>,
+    path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+struct RestoreHdWallet {
+    #[schema(value_type = String)]
+    mnemonic: Secret<String>,
+    #[serde(default)]
+    #[schema(value_type = String)]
+    passphrase: Secret<String>,
+    path: Option<String>,
+    // Unused addresses in a row before restoring stops, at most MAX_GAP_LIMIT
+    gap_limit: Option<u32>,
+}
+
+fn parse_address(address: &str) -> Result<Address, ApiError> {
+    address.parse().map_err(ApiError::bad_request)
+}
+
+fn derivation_path(path: &Option<String>) -> Result<DerivationPath, ApiError> {
+    path.as_deref().unwrap_or(DEFAULT_DERIVATION_PATH).parse().map_err(ApiError::bad_request)
+}
+
+#[utoipa::path(
+    post,
+    path = "/wallet/hd/new",
+    tag = "wallets",
+    request_body(content = Option<CreateHdWallet>),
+    responses(
+        (status = 200, body = ApiResponse<NewHdWallet>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Rate limited, retry_after_secs is set")
+    )
+)]
+async fn create_hd_wallet(payload: Option<Json<CreateHdWallet>>) -> ApiResult<NewHdWallet> {
+    let Json(payload) = payload.unwrap_or_default();
+    let path = derivation_path(&payload.path)?;
+    let hd_wallet = HdWallet::generate(payload.word_count.unwrap_or(12), path).map_err(ApiError::bad_request)?;
+    let first = hd_wallet.derive(0).map_err(ApiError::internal)?;
+    Ok(
+        ApiResponse::success(NewHdWallet {
+            mnemonic: hd_wallet.mnemonic(),
+            path: hd_wallet.path().to_string(),
+            address: first.address(),
+            public_key: first.public_key().to_string(),
+        })
+    )
+}
+
+// Rederive every address of a mnemonic that has been used on chain
+#[utoipa::path(
+    post,
+    path = "/wallet/hd/restore",
+    tag = "wallets",
+    request_body = RestoreHdWallet,
+    responses(
+        (status = 200, body = ApiResponse<RestoredHdWallet>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Rate limited, retry_after_secs is set")
+    )
+)]
+async fn restore_hd_wallet(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Json(payload): Json<RestoreHdWallet>
+) -> ApiResult<RestoredHdWallet> {
+    let path = derivation_path(&payload.path)?;
+    let gap_limit = payload.gap_limit.unwrap_or(DEFAULT_GAP_LIMIT);
+    if gap_limit == 0 || gap_limit > MAX_GAP_LIMIT {
+        return Err(ApiError::bad_request(format!("gap_limit must be between 1 and {}", MAX_GAP_LIMIT)));
     }
+    // Only the balances are needed; derivation is slow and must not hold the lock or the runtime
+    let balances = data.lock_timed().await.accounts.balances.clone();
+    let restored = task::spawn_blocking(move || {
+        let hd_wallet = HdWallet::restore(payload.mnemonic.expose(), payload.passphrase.expose(), path)?;
+        let wallets = hd_wallet.restore_addresses(&balances, gap_limit)?;
+        let addresses = wallets
+            .iter()
+            .zip(0..)
+            .map(|(wallet, index)| HdAddress {
+                index,
+                address: wallet.address(),
+                public_key: wallet.public_key().to_string(),
+                balance: balances.get(&wallet.address()).copied().unwrap_or(0.0),
+            })
+            .collect();
+        anyhow::Ok(RestoredHdWallet { path: hd_wallet.path().to_string(), addresses })
+    }).await;
+    let restored = restored.map_err(ApiError::internal)?.map_err(ApiError::bad_request)?;
+    Ok(ApiResponse::success(restored))
 }
 
-async fn create_wallet(State(mut data): State<Arc<Mutex<Blockchain>>>) -> Result<
-    impl IntoResponse,
-    (StatusCode, Json<serde_json::Value>)
-> {
-    let mut blockchain = data.lock().await;
-    let (pk, sk) = Wallet::generate_wallet();
+fn wallet_summary(blockchain: &Blockchain, public_key: &PublicKey) -> WalletSummary {
+    let address = Address::from_public_key(public_key);
+    WalletSummary {
+        address,
+        public_key: public_key.to_string(),
+        balance: blockchain.get_balance(&address),
+        nonce: blockchain.next_nonce(&address),
+    }
+}
 
-    blockchain.accounts.initialize(&pk.to_string());
+// The node's own wallet, which also receives mining rewards unless configured otherwise
+#[utoipa::path(get, path = "/wallet", tag = "wallets", responses((status = 200, body = ApiResponse<WalletSummary>)))]
+async fn get_node_wallet(State(data): State<Arc<Mutex<Blockchain>>>) -> ApiResult<WalletSummary> {
+    let blockchain = data.lock_timed().await;
+    Ok(ApiResponse::success(wallet_summary(&blockchain, &blockchain.wallet.public_key())))
+}
 
-    // blockchain.accounts.initialize(&receiver_pk.to_string());
-    let json_response =
-        serde_json::json!({
-        "status": "success",
-        "data": {
-            "public_key": pk.to_string(),
-            "secret_key": format!("{}", sk.display_secret()),
-        }
-    });
-    Ok(Json(json_response))
-}
-
-async fn get_wallet_details(State(data): State<Arc<Mutex<Blockchain>>>) -> Result<
-    impl IntoResponse,
-    (StatusCode, Json<serde_json::Value>)
-> {
-    // let b = Arc::make_mut(&mut data);
-    let mut blockchain = data.lock().await;
-    let wallet = &mut blockchain.wallet;
-
-    let json_response =
-        serde_json::json!({
-            "status": "success",
-            "data": {
-                "public_key": &wallet.get_public_key(),
-            }
-        });
-    Ok(Json(json_response))
+#[utoipa::path(
+    get,
+    path = "/wallet/{public_key}",
+    tag = "wallets",
+    params(("public_key" = String, Path, description = "Compressed public key, hex encoded")),
+    responses((status = 200, body = ApiResponse<WalletSummary>), (status = 400, body = ApiError))
+)]
+async fn get_wallet_details(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Path(public_key): Path<String>
+) -> ApiResult<WalletSummary> {
+    let public_key = PublicKey::from_str(&public_key).map_err(|_| ApiError::bad_request("Invalid public key"))?;
+    let blockchain = data.lock_timed().await;
+    Ok(ApiResponse::success(wallet_summary(&blockchain, &public_key)))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+struct NodeTransfer {
+    to_address: String,
+    amount: f64,
+}
+
+// Spend from the node wallet; the key never leaves the node
+#[utoipa::path(
+    post,
+    path = "/wallet/transfer",
+    tag = "wallets",
+    request_body = NodeTransfer,
+    responses(
+        (status = 200, body = ApiResponse<TransactionReceipt>),
+        (status = 400, body = ApiError),
+        (status = 403, body = ApiError)
+    )
+)]
+async fn transfer_from_node_wallet(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Extension(role): Extension<auth::Role>,
+    Json(payload): Json<NodeTransfer>
+) -> ApiResult<TransactionReceipt> {
+    // Checked here as well as in the auth layer, so a routing change cannot open the node wallet
+    if role < auth::Role::Admin {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Spending from the node wallet needs the admin role"));
+    }
+    let to_address = parse_address(&payload.to_address)?;
+    let mut blockchain = data.lock_timed().await;
+    let wallet = blockchain.wallet.clone();
+    let tx = wallet.transfer(&mut blockchain, to_address, payload.amount).map_err(ApiError::bad_request)?;
+    Ok(ApiResponse::success(TransactionReceipt { tx }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/wallet/{address}/balance",
+    tag = "wallets",
+    params(("address" = String, Path)),
+    responses((status = 200, body = ApiResponse<Balance>), (status = 400, body = ApiError))
+)]
 async fn get_wallet_balance(
     State(data): State<Arc<Mutex<Blockchain>>>,
     Path(address): Path<String>
-    // Json(payload): Json<String>
-) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
-    debug!("Received payload: {}", address);
-    let blockchain = data.lock().await;
-
-    if !blockchain.accounts.is_valid_address(&address) {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({"error": "Invalid Address"})),
-        ));
+) -> ApiResult<Balance> {
+    let address = parse_address(&address)?;
+    let blockchain = data.lock_timed().await;
+    Ok(ApiResponse::success(Balance { address, balance: blockchain.get_balance(&address) }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/wallet/{address}/transactions",
+    tag = "wallets",
+    params(("address" = String, Path)),
+    responses((status = 200, body = ApiResponse<WalletTransactions>), (status = 400, body = ApiError))
+)]
+async fn get_wallet_transactions(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Path(address): Path<String>
+) -> ApiResult<WalletTransactions> {
+    let address = parse_address(&address)?;
+    let blockchain = data.lock_timed().await;
+    let transactions = blockchain
+        .transactions_for(&address)
+        .into_iter()
+        .map(|(position, tx)| ChainTransaction::new(position, tx))
+        .collect();
+    Ok(ApiResponse::success(WalletTransactions { address, transactions }))
+}
+
+#[utoipa::path(get, path = "/blocks", tag = "blocks", responses((status = 200, body = ApiResponse<Vec<Block>>)))]
+async fn get_all_blocks(State(data): State<Arc<Mutex<Blockchain>>>) -> ApiResult<Vec<Block>> {
+    let blockchain = data.lock_timed().await;
+    Ok(ApiResponse::success(blockchain.get_all_blocks()))
+}
+
+// Most headers returned by one /headers request
+pub const MAX_HEADERS_PER_REQUEST: usize = 500;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HeadersQuery {
+    /// First height, 0 by default
+    #[serde(default)]
+    from: usize,
+    /// At most 500
+    limit: Option<usize>,
+}
+
+// Headers from a height onwards, enough for a light client to follow the chain
+#[utoipa::path(
+    get,
+    path = "/headers",
+    tag = "blocks",
+    params(HeadersQuery),
+    responses((status = 200, body = ApiResponse<Headers>))
+)]
+async fn get_headers(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Query(query): Query<HeadersQuery>
+) -> ApiResult<Headers> {
+    let limit = query.limit.unwrap_or(MAX_HEADERS_PER_REQUEST).min(MAX_HEADERS_PER_REQUEST);
+    let blockchain = data.lock_timed().await;
+    let headers: Vec<BlockHeader> = blockchain.chain
+        .iter()
+        .skip(query.from)
+        .take(limit)
+        .map(|block| block.header())
+        .collect();
+    Ok(ApiResponse::success(Headers { height: blockchain.height(), headers }))
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct StateProofQuery {
+    /// Block height to prove against, the tip when omitted
+    height: Option<usize>,
+}
+
+// Merkle proof of an account's balance and nonce against the state root of a block
+#[utoipa::path(
+    get,
+    path = "/state/proof/{address}",
+    tag = "state",
+    params(("address" = String, Path), StateProofQuery),
+    responses(
+        (status = 200, body = ApiResponse<StateProof>),
+        (status = 400, body = ApiError),
+        (status = 404, body = ApiError)
+    )
+)]
+async fn get_state_proof(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Path(address): Path<String>,
+    Query(query): Query<StateProofQuery>
+) -> ApiResult<StateProof> {
+    let address = parse_address(&address)?;
+    let blockchain = data.lock_timed().await;
+    let height = query.height.unwrap_or(blockchain.height());
+    let (block, accounts) = match (blockchain.chain.get(height), blockchain.accounts_at(height)) {
+        (Some(block), Some(accounts)) => (block, accounts),
+        _ => {
+            return Err(ApiError::not_found(format!("No block at height {}", height)));
+        }
+    };
+    let proof = accounts.state_proof(&address).ok_or_else(|| {
+        ApiError::not_found(format!("{} has no state at height {}", address, height))
+    })?;
+    Ok(
+        ApiResponse::success(StateProof {
+            address,
+            balance: accounts.get_balance(&address),
+            nonce: accounts.get_nonce(&address),
+            leaf: accounts.state_leaf(&address),
+            proof,
+            height,
+            block_hash: block.hash.clone(),
+            state_root: block.state_root.clone(),
+        })
+    )
+}
+
+// Chain summary together with the node's networking and runtime state
+#[utoipa::path(get, path = "/status", tag = "node", responses((status = 200, body = ApiResponse<NodeStatus>)))]
+async fn get_status(State(state): State<StatusState>) -> ApiResult<NodeStatus> {
+    let peers = state.p2p.peer_count().await;
+    let sync = state.p2p.sync_progress().await;
+    let blockchain = state.blockchain.lock_timed().await;
+    Ok(
+        ApiResponse::success(NodeStatus {
+            chain: blockchain.chain_info(),
+            total_supply: blockchain.total_supply(),
+            average_block_time_secs: blockchain.average_block_time(HASHRATE_WINDOW),
+            network_hashrate: blockchain.network_hashrate(HASHRATE_WINDOW),
+            peers,
+            sync,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: state.started.elapsed().as_secs(),
+        })
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+struct FaucetRequest {
+    address: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/faucet",
+    tag = "faucet",
+    request_body = FaucetRequest,
+    responses(
+        (status = 200, body = ApiResponse<TransactionReceipt>),
+        (status = 400, body = ApiError),
+        (status = 429, body = ApiError, description = "Paid out recently, retry_after_secs is set")
+    )
+)]
+async fn request_faucet(
+    State(state): State<FaucetState>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Json(payload): Json<FaucetRequest>
+) -> ApiResult<TransactionReceipt> {
+    let address = parse_address(&payload.address)?;
+    let mut faucet = state.faucet.lock().await;
+    let mut blockchain = state.blockchain.lock_timed().await;
+    match faucet.payout(&mut blockchain, address, client.ip()) {
+        Ok(tx) => Ok(ApiResponse::success(TransactionReceipt { tx })),
+        Err(FaucetError::TooSoon(retry_after)) => {
+            Err(ApiError {
+                retry_after_secs: Some(retry_after),
+                ..ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Faucet already paid out recently")
+            })
+        }
+        Err(FaucetError::Rejected(e)) => Err(ApiError::bad_request(e)),
     }
-    // let (public_key,) = params.0;
-    // let blockchain = state.0.borrow_mut(); // Borrow mutable reference to the blockchain
-
-    // Retrieve wallet balance from the blockchain
-    // let b = Arc::make_mut(&mut data);
-    let balance = blockchain.get_balance(&address);
-
-    // if let Some(balance) = blockchain.get_balance(&public_key.to_string()) {
-    let json_response =
-        serde_json::json!({
-            "status": "success",
-            "data": {
-                "public_key": address,
-                "balance": balance,
-            }
-        });
-    Ok(Json(json_response))
-    // } else {
-    //     Err(StatusCode::NOT_FOUND)
-    // }
-}
-
-async fn get_all_blocks(State(data): State<Arc<Mutex<Blockchain>>>) -> Result<
-    impl IntoResponse,
-    StatusCode
-> {
-    let blockchain = data.lock().await;
-    let blocks = blockchain.get_all_blocks();
-    let json_response =
-        serde_json::json!({
-            "status": "success",
-            "data": blocks
-        });
-    Ok(Json(json_response))
 }
@@ -0,0 +1,264 @@
+use crate::address::Address;
+use crate::api::ApiError;
+use crate::auth::Role;
+use crate::blockchain::Blockchain;
+use crate::metrics::BlockchainLock;
+use crate::rate_limit::ClientQuota;
+use crate::transaction::Transaction;
+use axum::{
+    body::Bytes,
+    extract::rejection::BytesRejection,
+    extract::State,
+    Extension,
+    http::StatusCode,
+    response::{ IntoResponse, Response },
+    routing::post,
+    Json,
+    Router,
+};
+use serde_json::{ json, Value };
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use utoipa::OpenApi;
+
+// Standard JSON-RPC 2.0 error codes, plus server errors for rejected, unauthorized and
+// rate limited requests
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const TRANSACTION_REJECTED: i64 = -32000;
+pub const UNAUTHORIZED: i64 = -32001;
+pub const RATE_LIMITED: i64 = -32002;
+
+const MAX_BATCH_SIZE: usize = 100;
+// Methods that change state take the chain lock for writing, so a batch may hold only a few
+const MAX_BATCH_WRITES: usize = 10;
+
+pub fn rpc_routes(app_state: Arc<Mutex<Blockchain>>) -> Router {
+    Router::new().route("/rpc", post(handle_rpc)).with_state(app_state)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(handle_rpc), tags((name = "rpc")))]
+pub struct RpcApi;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+// A single request object or a batch; notifications (no id) get no response
+#[utoipa::path(
+    post,
+    path = "/rpc",
+    tag = "rpc",
+    description = "JSON-RPC 2.0, single or batched. Methods: get_block_by_height, get_block_by_hash, \
+        get_transaction, get_balance, send_raw_transaction, get_mempool, get_chain_info, estimate_fee. \
+        A batch holds at most 100 requests, of which at most 10 send_raw_transaction; each of those counts \
+        against the /transaction/submit rate limit",
+    request_body(content = Object, description = "Request object or array of them"),
+    responses(
+        (status = 200, description = "Response object or array of them, errors included", body = Object),
+        (status = 204, description = "Only notifications were sent")
+    )
+)]
+async fn handle_rpc(
+    State(data): State<Arc<Mutex<Blockchain>>>,
+    Extension(role): Extension<Role>,
+    Extension(quota): Extension<ClientQuota>,
+    body: Result<Bytes, BytesRejection>
+) -> Response {
+    // Only a body over the size limit gets here; it is answered like any other HTTP error
+    let body = match body {
+        Ok(body) => body,
+        Err(rejection) => {
+            return ApiError::new(rejection.status(), rejection.body_text()).into_response();
+        }
+    };
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()))).into_response();
+        }
+    };
+    match request {
+        Value::Array(batch) if batch.is_empty() || batch.len() > MAX_BATCH_SIZE => {
+            let message = format!("Batch must hold between 1 and {} requests", MAX_BATCH_SIZE);
+            Json(error_response(Value::Null, RpcError::new(INVALID_REQUEST, message))).into_response()
+        }
+        Value::Array(batch) if batch.iter().filter(|request| is_write(request)).count() > MAX_BATCH_WRITES => {
+            let message = format!("Batch may hold at most {} send_raw_transaction requests", MAX_BATCH_WRITES);
+            Json(error_response(Value::Null, RpcError::new(INVALID_REQUEST, message))).into_response()
+        }
+        Value::Array(batch) => {
+            let mut responses = vec![];
+            for request in batch {
+                if let Some(response) = handle_request(&data, role, &quota, request).await {
+                    responses.push(response);
+                }
+            }
+            match responses.is_empty() {
+                true => StatusCode::NO_CONTENT.into_response(),
+                false => Json(Value::Array(responses)).into_response(),
+            }
+        }
+        request =>
+            match handle_request(&data, role, &quota, request).await {
+                Some(response) => Json(response).into_response(),
+                None => StatusCode::NO_CONTENT.into_response(),
+            }
+    }
+}
+
+async fn handle_request(
+    data: &Arc<Mutex<Blockchain>>,
+    role: Role,
+    quota: &ClientQuota,
+    request: Value
+) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let valid_id = matches!(id, None | Some(Value::Null | Value::Number(_) | Value::String(_)));
+    let version = request.get("jsonrpc").and_then(Value::as_str);
+    let method = match (version, request.get("method").and_then(Value::as_str)) {
+        (Some("2.0"), Some(method)) if valid_id => method,
+        _ => {
+            let id = id.filter(|_| valid_id).unwrap_or(Value::Null);
+            return Some(error_response(id, RpcError::new(INVALID_REQUEST, "Invalid JSON-RPC 2.0 request")));
+        }
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let result = match required_role(method) {
+        required if role < required => {
+            Err(RpcError::new(UNAUTHORIZED, format!("Method '{}' needs the {} role", method, required)))
+        }
+        _ =>
+            match quota_route(method).map(|route| quota.charge(route)) {
+                Some(Err(retry_after)) => {
+                    let message = format!("Rate limit exceeded, retry after {} seconds", retry_after);
+                    Err(RpcError::new(RATE_LIMITED, message))
+                }
+                _ => call(data, method, &params).await,
+            }
+    };
+    // Notifications are executed but never answered
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(e) => error_response(id, e),
+    })
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": error.code, "message": error.message },
+        "id": id,
+    })
+}
+
+// Parameter by position or by name, whichever the caller used
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a Value, RpcError> {
+    let value = match params {
+        Value::Array(values) => values.get(index),
+        Value::Object(values) => values.get(name),
+        _ => None,
+    };
+    value.ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Missing parameter '{}'", name)))
+}
+
+fn string_param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a str, RpcError> {
+    param(params, index, name)?
+        .as_str()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Parameter '{}' must be a string", name)))
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+fn is_write(request: &Value) -> bool {
+    request
+        .get("method")
+        .and_then(Value::as_str)
+        .is_some_and(|method| required_role(method) > Role::Read)
+}
+
+// HTTP route whose quota a method is charged against, so RPC is no way around it
+fn quota_route(method: &str) -> Option<&'static str> {
+    match method {
+        "send_raw_transaction" => Some("/transaction/submit"),
+        _ => None,
+    }
+}
+
+// The HTTP layer only lets callers with the read role reach /rpc
+fn required_role(method: &str) -> Role {
+    match method {
+        "send_raw_transaction" => Role::Submit,
+        _ => Role::Read,
+    }
+}
+
+// Lookups that find nothing return null rather than an error
+async fn call(data: &Arc<Mutex<Blockchain>>, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        "get_block_by_height" => {
+            let height = param(params, 0, "height")?
+                .as_u64()
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Parameter 'height' must be a number"))?;
+            let blockchain = data.lock_timed().await;
+            to_value(blockchain.chain.get(height as usize))
+        }
+        "get_block_by_hash" => {
+            let hash = string_param(params, 0, "hash")?;
+            let blockchain = data.lock_timed().await;
+            to_value(
+                blockchain.block_by_hash(hash).map(|(height, block)| json!({ "height": height, "block": block }))
+            )
+        }
+        "get_transaction" => {
+            let hash = string_param(params, 0, "hash")?;
+            let blockchain = data.lock_timed().await;
+            to_value(
+                blockchain
+                    .find_transaction(hash)
+                    .map(|(position, tx)| json!({ "height": position.height, "index": position.index, "tx": tx }))
+            )
+        }
+        "get_balance" => {
+            let address: Address = string_param(params, 0, "address")?
+                .parse()
+                .map_err(|e: anyhow::Error| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            let blockchain = data.lock_timed().await;
+            Ok(
+                json!({
+                "address": address,
+                "balance": blockchain.get_balance(&address),
+                "nonce": blockchain.next_nonce(&address),
+            })
+            )
+        }
+        "send_raw_transaction" => {
+            let transaction: Transaction = serde_json
+                ::from_value(param(params, 0, "transaction")?.clone())
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid transaction: {}", e)))?;
+            let mut blockchain = data.lock_timed().await;
+            let tx = blockchain
+                .submit_transaction(transaction)
+                .map_err(|e| RpcError::new(TRANSACTION_REJECTED, e.to_string()))?;
+            to_value(tx)
+        }
+        "get_mempool" => to_value(&data.lock_timed().await.pending_transactions),
+        "get_chain_info" => to_value(data.lock_timed().await.chain_info()),
+        "estimate_fee" => Ok(json!({ "fee": data.lock_timed().await.estimate_fee() })),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Method '{}' not found", method))),
+    }
+}
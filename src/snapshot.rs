@@ -0,0 +1,182 @@
+use crate::account::Account;
+use crate::block::Block;
+use crate::blockchain::{ Blockchain, ChainEvent };
+use crate::metrics::BlockchainLock;
+use anyhow::Context;
+use serde::{ Deserialize, Serialize };
+use std::{ fs, path::{ Path, PathBuf }, sync::Arc };
+use tokio::sync::{ broadcast, Mutex };
+use tracing::{ error, info, warn };
+
+// Account state as of a block, so a restart only has to replay the blocks after it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub chain_id: String,
+    pub height: usize,
+    pub block_hash: String,
+    pub state_root: String,
+    pub accounts: Account,
+}
+
+impl Snapshot {
+    pub fn capture(blockchain: &Blockchain) -> anyhow::Result<Self> {
+        let tip = blockchain.get_latest_block().ok_or_else(|| anyhow::Error::msg("Empty chain"))?;
+        Ok(Snapshot {
+            chain_id: blockchain.genesis.chain_id.clone(),
+            height: blockchain.height(),
+            block_hash: tip.hash.clone(),
+            state_root: blockchain.accounts.state_root(),
+            accounts: blockchain.accounts.clone(),
+        })
+    }
+
+    // The snapshot must commit to its own accounts and match the state root of its block
+    pub fn verify(&self, chain_id: &str, chain: &[Block]) -> anyhow::Result<()> {
+        if self.chain_id != chain_id {
+            return Err(anyhow::anyhow!("Snapshot belongs to chain {}", self.chain_id));
+        }
+        if self.accounts.state_root() != self.state_root {
+            return Err(anyhow::Error::msg("Snapshot accounts do not match its state root"));
+        }
+        match chain.get(self.height) {
+            Some(block) if block.hash == self.block_hash && block.state_root == self.state_root => Ok(()),
+            Some(block) if block.hash == self.block_hash => {
+                Err(anyhow::anyhow!("State root differs from the one committed in block {}", self.block_hash))
+            }
+            Some(_) => Err(anyhow::anyhow!("Block at height {} is not {}", self.height, self.block_hash)),
+            None => Err(anyhow::anyhow!("Chain does not reach snapshot height {}", self.height)),
+        }
+    }
+}
+
+// Snapshots kept as one JSON file per height under the data directory
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn open(data_dir: &Path) -> Self {
+        SnapshotStore { dir: data_dir.join("snapshots") }
+    }
+
+    pub fn save(&self, snapshot: &Snapshot) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("snapshot-{:010}.json", snapshot.height));
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, path: &Path) -> anyhow::Result<Snapshot> {
+        let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_slice(&contents).with_context(|| format!("Corrupt snapshot {}", path.display()))
+    }
+
+    // Snapshot files, newest first
+    pub fn list(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut paths: Vec<PathBuf> = fs
+            ::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+                name.starts_with("snapshot-") && name.ends_with(".json")
+            })
+            .collect();
+        paths.sort();
+        paths.reverse();
+        Ok(paths)
+    }
+
+    // Newest snapshot that verifies against the stored blocks; broken ones are skipped
+    pub fn latest_for(&self, chain_id: &str, chain: &[Block]) -> anyhow::Result<Option<Snapshot>> {
+        for path in self.list()? {
+            let checked = self.load(&path).and_then(|snapshot| {
+                snapshot.verify(chain_id, chain)?;
+                Ok(snapshot)
+            });
+            match checked {
+                Ok(snapshot) => {
+                    return Ok(Some(snapshot));
+                }
+                Err(e) => warn!("Ignoring snapshot {}: {:#}", path.display(), e),
+            }
+        }
+        Ok(None)
+    }
+
+    // Keep only the newest `keep` snapshots
+    pub fn prune(&self, keep: usize) -> anyhow::Result<usize> {
+        let stale = self.list()?.into_iter().skip(keep).collect::<Vec<_>>();
+        for path in &stale {
+            fs::remove_file(path)?;
+        }
+        Ok(stale.len())
+    }
+}
+
+// Rebuild the chain from stored blocks, taking account state from a snapshot instead of
+// replaying everything: blocks up to the snapshot only have their linkage and proof of
+// work checked, the ones after it are fully validated
+pub fn restore(blockchain: &mut Blockchain, blocks: Vec<Block>, snapshot: &Snapshot) -> anyhow::Result<()> {
+    if blocks.first().map(|block| block.hash.as_str()) != Some(blockchain.genesis_hash().as_str()) {
+        return Err(anyhow::Error::msg("Stored chain was created from a different genesis spec"));
+    }
+    snapshot.verify(&blockchain.genesis.chain_id, &blocks)?;
+    let headers: Vec<_> = blocks[1..=snapshot.height]
+        .iter()
+        .map(|block| block.header())
+        .collect();
+    blockchain.validate_headers(&blockchain.genesis_hash(), &headers)?;
+
+    let mut blocks = blocks.into_iter();
+    blockchain.chain = blocks.by_ref().take(snapshot.height + 1).collect();
+    blockchain.accounts = snapshot.accounts.clone();
+    for (height, block) in (snapshot.height + 1..).zip(blocks) {
+        blockchain.add_block(block).with_context(|| format!("Invalid stored block at height {}", height))?;
+    }
+    Ok(())
+}
+
+// Snapshot whenever the chain crosses a multiple of `interval` while the node runs
+pub async fn run(blockchain: Arc<Mutex<Blockchain>>, store: SnapshotStore, interval: usize, keep: usize) {
+    let (mut events, mut last_height) = {
+        let mut blockchain = blockchain.lock_timed().await;
+        (blockchain.subscribe(), blockchain.height())
+    };
+    loop {
+        match events.recv().await {
+            Ok(ChainEvent::BlockConnected { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Ok(_) => {
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                return;
+            }
+        }
+        let snapshot = {
+            let blockchain = blockchain.lock_timed().await;
+            let height = blockchain.height();
+            if height < last_height {
+                // Reorged below the last snapshot, which startup will now ignore
+                last_height = height;
+            }
+            if height / interval <= last_height / interval {
+                continue;
+            }
+            last_height = height;
+            Snapshot::capture(&blockchain)
+        };
+        let result = snapshot.and_then(|snapshot| {
+            let path = store.save(&snapshot)?;
+            info!("Saved snapshot at height {} to {}", snapshot.height, path.display());
+            store.prune(keep)
+        });
+        if let Err(e) = result {
+            error!("Failed to write snapshot: {:#}", e);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::block::Block;
+use crate::blockchain::{ Blockchain, ChainEvent };
+use crate::metrics::BlockchainLock;
+use anyhow::Context;
+use std::{ fs::{ self, File, OpenOptions }, io::{ BufRead, BufReader, Write }, path::{ Path, PathBuf }, sync::Arc };
+use tokio::{ sync::{ broadcast, Mutex }, task };
+use tracing::{ error, info };
+
+// Blocks stored one JSON object per line, genesis first, in the node's data directory
+pub struct ChainStore {
+    path: PathBuf,
+}
+
+impl ChainStore {
+    pub fn open(data_dir: &Path) -> Self {
+        ChainStore::at(data_dir.join("blocks.jsonl"))
+    }
+
+    // Store backed by an arbitrary file, e.g. an export
+    pub fn at(path: PathBuf) -> Self {
+        ChainStore { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> anyhow::Result<Vec<Block>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let file = File::open(&self.path).with_context(|| format!("Failed to open {}", self.path.display()))?;
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .map(|(line_number, line)| {
+                let line = line?;
+                serde_json
+                    ::from_str(&line)
+                    .with_context(|| format!("Corrupt block at {}:{}", self.path.display(), line_number + 1))
+            })
+            .collect()
+    }
+
+    // Blocks up to the first unreadable line, along with the error that stopped reading
+    pub fn load_readable(&self) -> anyhow::Result<(Vec<Block>, Option<anyhow::Error>)> {
+        if !self.path.exists() {
+            return Ok((vec![], None));
+        }
+        let file = File::open(&self.path).with_context(|| format!("Failed to open {}", self.path.display()))?;
+        let mut blocks = vec![];
+        for (line_number, line) in BufReader::new(file).lines().enumerate() {
+            let parsed = line
+                .map_err(anyhow::Error::from)
+                .and_then(|line| serde_json::from_str(&line).map_err(anyhow::Error::from));
+            match parsed {
+                Ok(block) => blocks.push(block),
+                Err(e) => {
+                    let e = e.context(format!("Corrupt block at {}:{}", self.path.display(), line_number + 1));
+                    return Ok((blocks, Some(e)));
+                }
+            }
+        }
+        Ok((blocks, None))
+    }
+
+    pub fn append(&self, blocks: &[Block]) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        for block in blocks {
+            writeln!(file, "{}", serde_json::to_string(block)?)?;
+        }
+        file.sync_data()?;
+        Ok(())
+    }
+
+    // Replace the whole file, used after a reorg; written aside first so a crash keeps the old chain
+    pub fn rewrite(&self, chain: &[Block]) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("jsonl.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            for block in chain {
+                writeln!(file, "{}", serde_json::to_string(block)?)?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+// Replay stored blocks on top of a freshly created chain, validating each one
+pub fn restore(blockchain: &mut Blockchain, blocks: Vec<Block>) -> anyhow::Result<()> {
+    let mut blocks = blocks.into_iter();
+    match blocks.next() {
+        Some(genesis) if genesis.hash != blockchain.genesis_hash() => {
+            return Err(anyhow::Error::msg("Stored chain was created from a different genesis spec"));
+        }
+        Some(_) => {}
+        None => {
+            return Ok(());
+        }
+    }
+    for (height, block) in blocks.enumerate() {
+        blockchain.add_block(block).with_context(|| format!("Invalid stored block at height {}", height + 1))?;
+    }
+    Ok(())
+}
+
+// Keep the store in step with the chain, appending new blocks or rewriting after a reorg. Blocks
+// are copied under the lock and written after releasing it, so a slow disk never holds up the chain
+pub async fn persist(blockchain: Arc<Mutex<Blockchain>>, store: ChainStore) {
+    let store = Arc::new(store);
+    let (mut events, chain) = {
+        let mut blockchain = blockchain.lock_timed().await;
+        (blockchain.subscribe(), blockchain.chain.clone())
+    };
+    let mut stored: Vec<String> = chain
+        .iter()
+        .map(|block| block.hash.clone())
+        .collect();
+    if let Err(e) = write(&store, chain, false).await {
+        error!("Failed to write chain to disk: {}", e);
+    }
+    info!("Persisting chain at height {}", stored.len().saturating_sub(1));
+
+    loop {
+        match events.recv().await {
+            Ok(ChainEvent::BlockConnected { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Ok(_) => {
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                return;
+            }
+        }
+        let (extends_stored, blocks) = {
+            let blockchain = blockchain.lock_timed().await;
+            let extends_stored = stored.len() <= blockchain.chain.len() &&
+                blockchain.chain[stored.len() - 1].hash == stored[stored.len() - 1];
+            match extends_stored {
+                true => (true, blockchain.chain[stored.len()..].to_vec()),
+                false => (false, blockchain.chain.clone()),
+            }
+        };
+        let hashes: Vec<String> = blocks
+            .iter()
+            .map(|block| block.hash.clone())
+            .collect();
+        match write(&store, blocks, extends_stored).await {
+            Ok(()) if extends_stored => stored.extend(hashes),
+            Ok(()) => {
+                stored = hashes;
+            }
+            Err(e) => error!("Failed to write chain to disk: {}", e),
+        }
+    }
+}
+
+// Append to or rewrite the store on a blocking thread, away from the async runtime
+async fn write(store: &Arc<ChainStore>, blocks: Vec<Block>, append: bool) -> anyhow::Result<()> {
+    let store = store.clone();
+    task::spawn_blocking(move || {
+        match append {
+            true => store.append(&blocks),
+            false => store.rewrite(&blocks),
+        }
+    }).await?
+}
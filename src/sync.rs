@@ -0,0 +1,244 @@
+use crate::block::{ Block, BlockHeader };
+use serde::{ Deserialize, Serialize };
+use std::{ collections::{ HashMap, HashSet }, net::SocketAddr, time::Instant };
+use tokio::time::Duration;
+use utoipa::ToSchema;
+
+// Headers returned for a single GetHeaders request
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+// Block bodies asked from one peer in a single GetBlockBodies request
+pub const BODIES_PER_REQUEST: usize = 16;
+// Upper bound on bodies requested but not yet received, across all peers
+const MAX_BODIES_IN_FLIGHT: usize = 256;
+// A body request older than this is handed to another peer
+const BODY_REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+// The sync is abandoned if nothing arrives for this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+pub enum SyncState {
+    Idle,
+    Headers,
+    Bodies,
+    Synced,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SyncProgress {
+    pub state: SyncState,
+    #[schema(value_type = Option<String>)]
+    pub peer: Option<SocketAddr>,
+    pub target_height: usize,
+    pub headers_downloaded: usize,
+    pub blocks_downloaded: usize,
+}
+
+// Headers-first initial block download: fetch and check the header chain from one
+// peer, then spread body downloads across every peer that is far enough ahead
+pub struct ChainSync {
+    pub state: SyncState,
+    pub peer: Option<SocketAddr>,
+    // Height of our block the downloaded headers build on
+    pub ancestor: usize,
+    pub target_height: usize,
+    pub headers: Vec<BlockHeader>,
+    bodies: HashMap<String, Block>,
+    in_flight: HashMap<String, (SocketAddr, Instant)>,
+    // Peers that answered a body request with none of the bodies asked for
+    unhelpful: HashSet<SocketAddr>,
+    // Index into `headers` of the next body to connect
+    next_to_connect: usize,
+    last_progress: Instant,
+}
+
+impl ChainSync {
+    pub fn new() -> Self {
+        ChainSync {
+            state: SyncState::Idle,
+            peer: None,
+            ancestor: 0,
+            target_height: 0,
+            headers: vec![],
+            bodies: HashMap::new(),
+            in_flight: HashMap::new(),
+            unhelpful: HashSet::new(),
+            next_to_connect: 0,
+            last_progress: Instant::now(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, SyncState::Headers | SyncState::Bodies)
+    }
+
+    pub fn start(&mut self, peer: SocketAddr, target_height: usize) {
+        *self = ChainSync::new();
+        self.state = SyncState::Headers;
+        self.peer = Some(peer);
+        self.target_height = target_height;
+    }
+
+    pub fn finish(&mut self, state: SyncState) {
+        let target_height = self.target_height;
+        *self = ChainSync::new();
+        self.state = state;
+        self.target_height = target_height;
+    }
+
+    pub fn progress(&self) -> SyncProgress {
+        SyncProgress {
+            state: self.state,
+            peer: self.peer,
+            target_height: self.target_height,
+            headers_downloaded: self.headers.len(),
+            blocks_downloaded: self.next_to_connect + self.bodies.len(),
+        }
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.is_active() && self.last_progress.elapsed() > STALL_TIMEOUT
+    }
+
+    // Append an already validated batch of headers
+    pub fn add_headers(&mut self, ancestor: usize, headers: Vec<BlockHeader>) {
+        if self.headers.is_empty() {
+            self.ancestor = ancestor;
+        }
+        self.headers.extend(headers);
+        self.target_height = self.target_height.max(self.ancestor + self.headers.len());
+        self.last_progress = Instant::now();
+    }
+
+    pub fn last_header_hash(&self) -> Option<String> {
+        self.headers.last().map(|header| header.hash.clone())
+    }
+
+    // Drop the downloaded headers after `hash`, for a peer that reorged between batches. False
+    // if `hash` is not among them
+    pub fn rewind_to(&mut self, hash: &str) -> bool {
+        match self.headers.iter().position(|header| header.hash == hash) {
+            Some(index) => {
+                self.headers.truncate(index + 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Locator for the next header batch: the downloaded headers newest first, dense near the
+    // end and sparse further back, then our own chain's locator in case the peer reorged below them
+    pub fn locator(&self, chain_locator: Vec<String>) -> Vec<String> {
+        let mut locator = Vec::new();
+        let mut index = self.headers.len() as isize - 1;
+        let mut step = 1;
+        while index >= 0 {
+            locator.push(self.headers[index as usize].hash.clone());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            index -= step;
+        }
+        locator.extend(chain_locator);
+        locator
+    }
+
+    pub fn begin_bodies(&mut self) {
+        self.state = SyncState::Bodies;
+        self.last_progress = Instant::now();
+    }
+
+    // Hand out missing bodies, one outstanding request per idle peer
+    pub fn next_body_requests(&mut self, peers: &[SocketAddr]) -> Vec<(SocketAddr, Vec<String>)> {
+        self.in_flight.retain(|_, (_, requested_at)| requested_at.elapsed() < BODY_REQUEST_TIMEOUT);
+        let busy: HashSet<SocketAddr> = self.in_flight
+            .values()
+            .map(|(peer, _)| *peer)
+            .collect();
+        let idle: Vec<SocketAddr> = peers
+            .iter()
+            .filter(|peer| !busy.contains(peer) && !self.unhelpful.contains(peer))
+            .copied()
+            .collect();
+        if idle.is_empty() {
+            return vec![];
+        }
+        let budget = MAX_BODIES_IN_FLIGHT.saturating_sub(self.in_flight.len());
+        let missing: Vec<String> = self.headers[self.next_to_connect..]
+            .iter()
+            .filter(|header| {
+                !self.bodies.contains_key(&header.hash) && !self.in_flight.contains_key(&header.hash)
+            })
+            .take(budget)
+            .map(|header| header.hash.clone())
+            .collect();
+
+        let now = Instant::now();
+        let mut requests = Vec::new();
+        for (peer, chunk) in idle.into_iter().zip(missing.chunks(BODIES_PER_REQUEST)) {
+            for hash in chunk {
+                self.in_flight.insert(hash.clone(), (peer, now));
+            }
+            requests.push((peer, chunk.to_vec()));
+        }
+        requests
+    }
+
+    // A peer answered its request: anything it did not deliver can go to someone else
+    pub fn release_peer(&mut self, peer: SocketAddr, delivered: usize) {
+        self.in_flight.retain(|_, (requested_from, _)| *requested_from != peer);
+        if delivered == 0 && Some(peer) != self.peer {
+            self.unhelpful.insert(peer);
+        }
+    }
+
+    // Accept a body only if it matches a header we asked for
+    pub fn add_body(&mut self, block: Block) -> anyhow::Result<()> {
+        let expected = self.headers
+            .iter()
+            .find(|header| header.hash == block.hash)
+            .ok_or_else(|| anyhow::Error::msg("Unrequested block body"))?;
+        if &block.header() != expected {
+            return Err(anyhow::Error::msg("Block body does not match its header"));
+        }
+        self.in_flight.remove(&block.hash);
+        self.bodies.insert(block.hash.clone(), block);
+        self.last_progress = Instant::now();
+        Ok(())
+    }
+
+    // Bodies that can be connected in order right now
+    pub fn take_ready(&mut self) -> Vec<Block> {
+        let mut ready = Vec::new();
+        while let Some(header) = self.headers.get(self.next_to_connect) {
+            match self.bodies.remove(&header.hash) {
+                Some(block) => {
+                    ready.push(block);
+                    self.next_to_connect += 1;
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+        ready
+    }
+
+    // Hash the next body to connect must build on
+    pub fn next_parent_hash(&self) -> Option<String> {
+        self.headers.get(self.next_to_connect).map(|header| header.previous_hash.clone())
+    }
+
+    pub fn has_all_bodies(&self) -> bool {
+        self.headers[self.next_to_connect..].iter().all(|header| self.bodies.contains_key(&header.hash))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_to_connect == self.headers.len()
+    }
+}
+
+impl Default for ChainSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
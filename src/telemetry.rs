@@ -0,0 +1,96 @@
+use axum::{
+    extract::{ MatchedPath, Request },
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use serde::{ Deserialize, Serialize };
+use std::{ fmt, str::FromStr, time::Instant };
+use tracing::{ debug, info_span, Instrument, Level };
+
+pub const LOG_FORMATS: [&str; 2] = ["text", "json"];
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Install the global subscriber; json writes one object per line with the span fields
+pub fn init(level: Level, format: &str) {
+    let subscriber = tracing_subscriber::fmt().with_max_level(level);
+    match format {
+        "json" => subscriber.json().with_current_span(true).with_span_list(true).init(),
+        _ => subscriber.init(),
+    }
+}
+
+// A value that must never reach the logs: Debug and Display print a placeholder, the
+// value itself is only reachable through `expose`
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl<T: FromStr> FromStr for Secret<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Secret)
+    }
+}
+
+// Run each request inside a span carrying its id, taken from the client's x-request-id
+// header when it sent a usable one, and echo the id back
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    // client is filled in by auth once an API key is recognised
+    let span = info_span!(
+        "request",
+        id = %id,
+        method = %request.method(),
+        route = %route,
+        client = tracing::field::Empty
+    );
+    async move {
+        let started = Instant::now();
+        let mut response = next.run(request).await;
+        debug!(
+            status = response.status().as_u16(),
+            elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+            "Handled request"
+        );
+        if let Ok(value) = HeaderValue::from_str(&id) {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        response
+    }
+        .instrument(span).await
+}
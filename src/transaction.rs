@@ -1,56 +1,204 @@
 use secp256k1::{ ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey };
 use serde::{ Deserialize, Serialize };
+use sha256::digest;
+use std::str::FromStr;
+use utoipa::ToSchema;
 
-use crate::{ account::Account, block::Block, blockchain::{ self, Blockchain } };
+use crate::account::Account;
+use crate::address::Address;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub enum TxStatus {
     PENDING,
     FAILED,
     SUCCESS,
 }
+
+// Case-insensitive, for query strings
+impl FromStr for TxStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(status: &str) -> anyhow::Result<Self> {
+        match status.to_ascii_uppercase().as_str() {
+            "PENDING" => Ok(TxStatus::PENDING),
+            "FAILED" => Ok(TxStatus::FAILED),
+            "SUCCESS" => Ok(TxStatus::SUCCESS),
+            _ => Err(anyhow::anyhow!("Unknown status '{}', expected pending, failed or success", status)),
+        }
+    }
+}
 // Transaction structure
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Transaction {
-    pub from_address: String,
-    pub to_address: String,
-    pub msg: String,
+    pub from_address: Address,
+    pub to_address: Address,
+    // The digest the signature covers, so it names exactly one transfer on one chain
+    pub id: String,
     pub amount: f64,
+    // Still carried so the signature can be checked against from_address. Compressed, hex encoded
+    #[schema(value_type = String)]
     pub pub_key: PublicKey,
+    // DER, hex encoded
+    #[schema(value_type = Option<String>)]
     pub signature: Option<Signature>, // Signature will be added during signing
     pub status: TxStatus,
     pub nonce: u32,
+    // Genesis block of the chain the transfer is meant for, so it cannot be replayed on another
+    pub genesis_hash: String,
 }
 
 impl Transaction {
+    // Build and sign a transfer from the key's own address
+    pub fn new_signed(
+        secret_key: &SecretKey,
+        genesis_hash: &str,
+        to_address: Address,
+        amount: f64,
+        nonce: u32
+    ) -> Self {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), secret_key);
+        let mut transaction = Transaction {
+            from_address: Address::from_public_key(&public_key),
+            to_address,
+            id: String::new(),
+            amount,
+            pub_key: public_key,
+            signature: None,
+            status: TxStatus::PENDING,
+            nonce,
+            genesis_hash: genesis_hash.to_string(),
+        };
+        transaction.sign_transaction(secret_key);
+        transaction
+    }
+
+    // Every field a relayer could otherwise rewrite; status is settled by the miner instead
+    pub fn signing_digest(&self) -> String {
+        digest(
+            format!(
+                "duma-tx:{}:{}:{}:{}:{}",
+                self.genesis_hash,
+                self.from_address,
+                self.to_address,
+                self.amount,
+                self.nonce
+            )
+        )
+    }
+
     pub fn sign_transaction(&mut self, secret_key: &SecretKey) {
-        let secp = Secp256k1::new();
-        let decode_message = hex::decode(&self.msg).expect("Failed to decode message");
-        let message: Message = Message::from_digest_slice(&decode_message).expect("32 bytes");
-        let sig = secp.sign_ecdsa(&message, &secret_key);
-        self.signature = Some(sig);
+        self.id = self.signing_digest();
+        let message = Message::from_digest(digest_bytes(&self.id));
+        self.signature = Some(Secp256k1::new().sign_ecdsa(&message, secret_key));
+    }
+
+    // Hash of the full transaction, used as the Merkle leaf
+    pub fn hash(&self) -> String {
+        digest(serde_json::to_string(self).expect("Transaction serializes to JSON"))
+    }
+
+    // Check the signature alone, without touching account state: it must be made by the key
+    // behind from_address over the digest of the fields as they are now
+    pub fn verify_signature(&self) -> bool {
+        let id = self.signing_digest();
+        if self.id != id || self.from_address != Address::from_public_key(&self.pub_key) {
+            return false;
+        }
+        let message = Message::from_digest(digest_bytes(&id));
+        match &self.signature {
+            Some(signature) => Secp256k1::verification_only().verify_ecdsa(&message, signature, &self.pub_key).is_ok(),
+            None => false,
+        }
     }
 
     // Validate transaction signature
     pub fn is_valid(&self, account: &Account) -> bool {
-        let secp = Secp256k1::verification_only();
-        let decode_message = hex::decode(&self.msg).expect("Failed to decode message");
-        let message: Message = Message::from_digest_slice(&decode_message).expect("32 bytes");
-        let sender_balance = account.get_balance(&self.from_address);
-        if sender_balance < &self.amount {
-            // println!("\n =====sender tx: {:?}", &self);
-            // println!("Sender account: {:?}", &sender_balance);
+        // Only a positive amount; verify_signature below ties the sender to the key
+        if !(self.amount > 0.0 && self.amount.is_finite()) {
             return false;
         }
-        if let Some(signature) = &self.signature {
-            // println!("with signature: {}", signature);
-            // Verify signature (not implemented here)
-            let verify = secp.verify_ecdsa(&message, signature, &self.pub_key).is_ok();
-            // println!("verify: {}", verify);
-            return verify;
-            // return verify_signature(self.calculate_hash().as_bytes(), signature);
-        } else {
+        // Each nonce can be spent once, so a signed transfer cannot be replayed
+        if self.nonce != account.get_nonce(&self.from_address) {
             return false;
         }
+        let sender_balance = account.get_balance(&self.from_address);
+        if sender_balance < self.amount {
+            return false;
+        }
+        self.verify_signature()
+    }
+}
+
+// Raw bytes of a hex SHA-256 digest computed by signing_digest
+fn digest_bytes(digest: &str) -> [u8; 32] {
+    hex::decode(digest).ok().and_then(|bytes| bytes.try_into().ok()).expect("SHA-256 hex digest")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    fn secret_key(secret: u8) -> SecretKey {
+        let mut bytes = [0u8; 32];
+        bytes[31] = secret;
+        SecretKey::from_slice(&bytes).unwrap()
+    }
+
+    fn transfer(amount: f64, nonce: u32) -> Transaction {
+        let to = "duma1k8ye8rcpzg0ptxy84skg6wf6ytjywmlce5h424".parse().unwrap();
+        Transaction::new_signed(&secret_key(1), GENESIS_HASH, to, amount, nonce)
+    }
+
+    #[test]
+    fn signing_digest_covers_chain_parties_amount_and_nonce() {
+        let txn = transfer(1.5, 7);
+        assert_eq!(txn.from_address.to_string(), "duma1pac4ht6afshdx2tctnhjnetz7u6g3j9z7vz07y");
+        assert_eq!(txn.signing_digest(), "1a131955b9f4bc34de7f668d29cf15d2ead5a7adcdb2186e6dd78a5a51bccd5f");
+        assert_eq!(txn.id, txn.signing_digest());
+    }
+
+    #[test]
+    fn signature_verifies_until_a_field_changes() {
+        assert!(transfer(1.5, 7).verify_signature());
+
+        let mut txn = transfer(1.5, 7);
+        txn.amount = 15.0;
+        assert!(!txn.verify_signature());
+
+        let mut txn = transfer(1.5, 7);
+        txn.nonce = 8;
+        assert!(!txn.verify_signature());
+
+        let mut txn = transfer(1.5, 7);
+        txn.to_address = txn.from_address;
+        assert!(!txn.verify_signature());
+
+        let mut txn = transfer(1.5, 7);
+        txn.genesis_hash = "1".repeat(64);
+        assert!(!txn.verify_signature());
+
+        // The status is left to the miner and not signed
+        let mut txn = transfer(1.5, 7);
+        txn.status = TxStatus::SUCCESS;
+        assert!(txn.verify_signature());
+    }
+
+    #[test]
+    fn signature_must_come_from_the_sender() {
+        // Re-signed by another key over the same fields
+        let mut txn = transfer(1.5, 7);
+        txn.sign_transaction(&secret_key(2));
+        assert!(!txn.verify_signature());
+
+        // A key swapped in that does not match from_address
+        let mut txn = transfer(1.5, 7);
+        txn.pub_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key(2));
+        assert!(!txn.verify_signature());
+
+        let mut txn = transfer(1.5, 7);
+        txn.signature = None;
+        assert!(!txn.verify_signature());
     }
 }
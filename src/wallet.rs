@@ -2,8 +2,12 @@ use crate::blockchain::Blockchain;
 use serde::{ Deserialize, Serialize };
 use secp256k1::{ rand, Secp256k1, PublicKey, SecretKey };
 use secp256k1::{ Keypair };
+use crate::address::Address;
+use crate::transaction::Transaction;
+use crate::keystore::Keystore;
+use std::{ fmt, path::Path, str::FromStr };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Wallet {
     pub key_pair: Keypair,
 }
@@ -18,26 +22,103 @@ impl Wallet {
         }
     }
 
+    pub fn from_secret_key(secret_key: &SecretKey) -> Wallet {
+        Wallet {
+            key_pair: Keypair::from_secret_key(&Secp256k1::new(), secret_key),
+        }
+    }
+
+    // Wallet from a hex encoded secret key, e.g. one being moved into a keystore
+    pub fn import_secret_key(secret_key: &str) -> anyhow::Result<Wallet> {
+        let secret_key = SecretKey::from_str(secret_key).map_err(|_|
+            anyhow::Error::msg("Invalid secret key format")
+        )?;
+        Ok(Wallet::from_secret_key(&secret_key))
+    }
+
+    // New random key, written encrypted to a keystore file
+    pub fn create_keystore(path: &Path, password: &str) -> anyhow::Result<Wallet> {
+        let wallet = Wallet::new();
+        wallet.save_keystore(path, password)?;
+        Ok(wallet)
+    }
+
+    pub fn import_keystore(keystore: &Keystore, password: &str) -> anyhow::Result<Wallet> {
+        Ok(Wallet::from_secret_key(&keystore.decrypt(password)?))
+    }
+
+    // Decrypt a keystore file with its password
+    pub fn unlock(path: &Path, password: &str) -> anyhow::Result<Wallet> {
+        Wallet::import_keystore(&Keystore::load(path)?, password)
+    }
+
+    pub fn export_keystore(&self, password: &str) -> anyhow::Result<Keystore> {
+        Keystore::encrypt(&self.secret_key(), password)
+    }
+
+    pub fn save_keystore(&self, path: &Path, password: &str) -> anyhow::Result<()> {
+        self.export_keystore(password)?.save(path)
+    }
+
     pub fn generate_wallet() -> (PublicKey, SecretKey) {
         let secp = Secp256k1::new();
         let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
-        // let keypair = Keypair::from_secret_key(&secp, &secret_key);
+        (public_key, secret_key)
+    }
 
-        let key_pair = Keypair::from_secret_key(&secp, &secret_key);
-        println!("Public key: {}", public_key);
-        println!("Your key pair is: {:?}", key_pair);
+    pub fn public_key(&self) -> PublicKey {
+        self.key_pair.public_key()
+    }
 
-        (public_key, secret_key)
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&self.public_key())
     }
 
-    pub fn get_public_key(&mut self) -> String {
-        let secp = Secp256k1::new();
-        let keypair = Keypair::new(&secp, &mut rand::thread_rng());
-        let public_key = PublicKey::from_keypair(&keypair);
-        public_key.to_string()
+    pub fn secret_key(&self) -> SecretKey {
+        self.key_pair.secret_key()
+    }
+
+    pub fn get_public_key(&self) -> String {
+        self.public_key().to_string()
+    }
+
+    pub fn get_balance(&self, blockchain: &Blockchain) -> f64 {
+        blockchain.get_balance(&self.address())
+    }
+
+    // Nonce our next transfer has to use, counting ones still in the mempool
+    pub fn get_nonce(&self, blockchain: &Blockchain) -> u32 {
+        blockchain.next_nonce(&self.address())
+    }
+
+    // Sign a transfer from this wallet and queue it in the mempool
+    pub fn transfer(
+        &self,
+        blockchain: &mut Blockchain,
+        to_address: Address,
+        amount: f64
+    ) -> anyhow::Result<Transaction> {
+        if !(amount > 0.0 && amount.is_finite()) {
+            return Err(anyhow::Error::msg("Amount must be positive"));
+        }
+        if amount > self.get_balance(blockchain) {
+            return Err(anyhow::Error::msg("Insufficient funds"));
+        }
+        let (genesis_hash, nonce) = (blockchain.genesis_hash(), self.get_nonce(blockchain));
+        let transaction = Transaction::new_signed(&self.secret_key(), &genesis_hash, to_address, amount, nonce);
+        blockchain.submit_transaction(transaction)
     }
+}
+
+// Only the address, so a wallet can be logged without leaking its key
+impl fmt::Debug for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wallet").field("address", &self.address()).finish()
+    }
+}
 
-    pub fn get_balance<'a>(&mut self, blockchain: &'a mut Blockchain) -> &'a f64 {
-        blockchain.get_balance(&self.get_public_key())
+impl Default for Wallet {
+    fn default() -> Self {
+        Self::new()
     }
 }